@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{Expression, Function, Statement},
+    intern::Symbol,
+};
+
+pub fn dependencies(statements: &[Statement]) -> HashMap<Symbol, HashSet<Symbol>> {
+    let mut dependencies = HashMap::new();
+    for statement in statements {
+        let mut collector = Collector::default();
+        let name = match statement {
+            Statement::Var(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    collector.expression(initializer);
+                }
+                name
+            }
+            Statement::Function(function) => {
+                collector.function(function);
+                &function.name
+            }
+            _ => continue,
+        };
+        dependencies.insert(*name, collector.globals);
+    }
+    dependencies
+}
+
+#[derive(Default)]
+struct Collector {
+    scopes: Vec<HashSet<Symbol>>,
+    globals: HashSet<Symbol>,
+}
+
+impl Collector {
+    fn function(&mut self, function: &Function) {
+        self.scopes.push(function.params.iter().cloned().collect());
+        for statement in &function.body {
+            self.statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Empty => {}
+            Statement::Expression(expression) | Statement::Print(expression) => {
+                self.expression(expression)
+            }
+            Statement::Var(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.declare(*name);
+            }
+            Statement::Block(statements) => {
+                self.scopes.push(HashSet::new());
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.scopes.pop();
+            }
+            Statement::If(condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Statement::While(condition, body) => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            Statement::For(initializer, condition, increment, body) => {
+                self.scopes.push(HashSet::new());
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                for expression in condition.iter().chain(increment) {
+                    self.expression(expression);
+                }
+                self.statement(body);
+                self.scopes.pop();
+            }
+            Statement::Break | Statement::Continue => {}
+            Statement::Function(function) => {
+                self.declare(function.name);
+                self.function(function);
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal(_) | Expression::This | Expression::Super(_) => {}
+            Expression::Unary(_, expression) => self.expression(&expression.value),
+            Expression::Binary(_, left, right) | Expression::Logical(_, left, right) => {
+                self.expression(&left.value);
+                self.expression(&right.value);
+            }
+            Expression::Call(callee, arguments) => {
+                self.expression(&callee.value);
+                for argument in arguments {
+                    self.expression(&argument.value);
+                }
+            }
+            Expression::Get(object, _) => self.expression(&object.value),
+            Expression::Index(list, index) => {
+                self.expression(&list.value);
+                self.expression(&index.value);
+            }
+            Expression::SetIndex(object, index, value) => {
+                self.expression(&object.value);
+                self.expression(&index.value);
+                self.expression(&value.value);
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(&key.value);
+                    self.expression(&value.value);
+                }
+            }
+            Expression::Comma(expressions) | Expression::List(expressions) => {
+                for expression in expressions {
+                    self.expression(&expression.value);
+                }
+            }
+            Expression::Variable(name) => self.reference(*name),
+            Expression::Assign(name, value) => {
+                self.expression(&value.value);
+                self.reference(*name);
+            }
+        }
+    }
+
+    fn reference(&mut self, name: Symbol) {
+        if !self.scopes.iter().any(|scope| scope.contains(&name)) {
+            self.globals.insert(name);
+        }
+    }
+
+    fn declare(&mut self, name: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    use crate::{
+        ast::{BinaryOperator, Node},
+        span::{Span, Spanned},
+        value::Value,
+    };
+
+    fn node(expression: Expression) -> Box<Node> {
+        Box::new(Spanned {
+            value: expression,
+            span: Span { start: 0, end: 0 },
+        })
+    }
+
+    #[test]
+    fn function_depends_on_global() {
+        // var a = 1; fun f() { return a; }
+        let statements = vec![
+            Statement::Var(
+                Symbol::intern("a"),
+                Some(Expression::Literal(Value::Number(1.0))),
+            ),
+            Statement::Function(Rc::new(Function {
+                name: Symbol::intern("f"),
+                params: vec![],
+                body: vec![Statement::Return(Some(Expression::Variable(
+                    Symbol::intern("a"),
+                )))],
+            })),
+        ];
+        let dependencies = dependencies(&statements);
+        assert_eq!(dependencies[&Symbol::intern("a")], HashSet::new());
+        assert_eq!(
+            dependencies[&Symbol::intern("f")],
+            HashSet::from([Symbol::intern("a")])
+        );
+    }
+
+    #[test]
+    fn locals_are_not_dependencies() {
+        // fun g(x) { var y = x; return y + z; }
+        let statements = vec![Statement::Function(Rc::new(Function {
+            name: Symbol::intern("g"),
+            params: vec![Symbol::intern("x")],
+            body: vec![
+                Statement::Var(
+                    Symbol::intern("y"),
+                    Some(Expression::Variable(Symbol::intern("x"))),
+                ),
+                Statement::Return(Some(Expression::Binary(
+                    BinaryOperator::Add,
+                    node(Expression::Variable(Symbol::intern("y"))),
+                    node(Expression::Variable(Symbol::intern("z"))),
+                ))),
+            ],
+        }))];
+        let dependencies = dependencies(&statements);
+        assert_eq!(
+            dependencies[&Symbol::intern("g")],
+            HashSet::from([Symbol::intern("z")])
+        );
+    }
+}
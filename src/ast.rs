@@ -1,29 +1,556 @@
-use crate::value::Value;
+use std::{
+    fmt::{self, Display},
+    rc::Rc,
+};
 
-#[derive(Debug)]
+use crate::{
+    intern::Symbol,
+    precedence::{Associativity, Precedence},
+    span::Spanned,
+    value::Value,
+};
+
+// A subexpression and the source it was parsed from.
+pub type Node = Spanned<Expression>;
+
+#[derive(PartialEq, Debug)]
 pub enum Expression {
     Literal(Value),
-    Grouping(Box<Expression>),
-    Unary(UnaryOperator, Box<Expression>),
-    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Unary(UnaryOperator, Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+    Logical(LogicalOperator, Box<Node>, Box<Node>),
+    Variable(Symbol),
+    Assign(Symbol, Box<Node>),
+    Call(Box<Node>, Vec<Node>),
+    Comma(Vec<Node>),
+    List(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Index(Box<Node>, Box<Node>),
+    SetIndex(Box<Node>, Box<Node>, Box<Node>),
+    Get(Box<Node>, Symbol),
+    This,
+    Super(Symbol),
+}
+
+impl Expression {
+    // Moves the expression out, leaving a `nil` literal in its place.
+    pub fn take(&mut self) -> Expression {
+        std::mem::replace(self, Expression::Literal(Value::Nil))
+    }
+
+    fn take_operands(&mut self, operands: &mut Vec<Expression>) {
+        let mut take = |node: &mut Node| operands.push(node.value.take());
+        match self {
+            Expression::Unary(_, operand)
+            | Expression::Assign(_, operand)
+            | Expression::Get(operand, _) => take(operand),
+            Expression::Binary(_, left, right)
+            | Expression::Logical(_, left, right)
+            | Expression::Index(left, right) => {
+                take(left);
+                take(right);
+            }
+            Expression::SetIndex(object, index, value) => {
+                take(object);
+                take(index);
+                take(value);
+            }
+            Expression::Call(callee, arguments) => {
+                take(callee);
+                arguments.iter_mut().for_each(take);
+            }
+            Expression::Comma(nodes) | Expression::List(nodes) => nodes.iter_mut().for_each(take),
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    take(key);
+                    take(value);
+                }
+            }
+            Expression::Literal(_)
+            | Expression::Variable(_)
+            | Expression::This
+            | Expression::Super(_) => {}
+        }
+    }
+}
+
+// Dropping operands recursively would overflow the stack on deeply nested
+// trees, so they're moved out and dropped from a heap-allocated stack.
+impl Drop for Expression {
+    fn drop(&mut self) {
+        let mut operands = Vec::new();
+        self.take_operands(&mut operands);
+        while let Some(mut operand) = operands.pop() {
+            operand.take_operands(&mut operands);
+        }
+    }
+}
+
+impl Node {
+    pub fn take(&mut self) -> Node {
+        Spanned {
+            value: self.value.take(),
+            span: self.span,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Statement {
+    Empty,
+    Expression(Expression),
+    Print(Expression),
+    Var(Symbol, Option<Expression>),
+    Block(Vec<Statement>),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    While(Expression, Box<Statement>),
+    For(
+        Option<Box<Statement>>,
+        Option<Expression>,
+        Option<Expression>,
+        Box<Statement>,
+    ),
+    Break,
+    Continue,
+    Function(Rc<Function>),
+    Return(Option<Expression>),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug)]
+pub struct Function {
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum UnaryOperator {
     Neg,
     Not,
+    Pos,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum BinaryOperator {
     Add,
     Sub,
     Div,
     Mul,
+    Pow,
     Equal,
     NotEqual,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+// Unlike binary operators, these evaluate their right operand only when the
+// left one doesn't already decide the result, and yield an operand's value.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LogicalOperator {
+    And,
+    Or,
+}
+
+impl Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOperator::Neg => "-",
+            UnaryOperator::Not => "!",
+            UnaryOperator::Pos => "+",
+        })
+    }
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Pow => "**",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::BitAnd => "&",
+            BinaryOperator::BitOr => "|",
+            BinaryOperator::BitXor => "^",
+            BinaryOperator::ShiftLeft => "<<",
+            BinaryOperator::ShiftRight => ">>",
+        })
+    }
+}
+
+impl Display for LogicalOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogicalOperator::And => "and",
+            LogicalOperator::Or => "or",
+        })
+    }
+}
+
+// Renders an expression in fully parenthesized prefix form, e.g.
+// `(* (- 1) (+ 2 3))`.
+pub fn print_ast(expression: &Expression) -> String {
+    let mut out = String::new();
+    write_ast(&mut out, expression);
+    out
+}
+
+fn write_ast(out: &mut String, expression: &Expression) {
+    match expression {
+        Expression::Literal(value) => out.push_str(&value.to_string()),
+        Expression::Variable(name) => out.push_str(name.as_str()),
+        Expression::Unary(operator, operand) => {
+            parenthesize(out, &operator.to_string(), [operand.as_ref()])
+        }
+        Expression::Binary(operator, left, right) => {
+            parenthesize(out, &operator.to_string(), [left.as_ref(), right.as_ref()])
+        }
+        Expression::Logical(operator, left, right) => {
+            parenthesize(out, &operator.to_string(), [left.as_ref(), right.as_ref()])
+        }
+        Expression::Assign(name, value) => {
+            out.push_str("(= ");
+            out.push_str(name.as_str());
+            out.push(' ');
+            write_ast(out, &value.value);
+            out.push(')');
+        }
+        Expression::Call(callee, arguments) => parenthesize(
+            out,
+            "call",
+            std::iter::once(callee.as_ref()).chain(arguments),
+        ),
+        Expression::Comma(expressions) => parenthesize(out, ",", expressions),
+        Expression::List(elements) => parenthesize(out, "list", elements),
+        Expression::Map(entries) => parenthesize(
+            out,
+            "map",
+            entries.iter().flat_map(|(key, value)| [key, value]),
+        ),
+        Expression::Index(list, index) => {
+            parenthesize(out, "index", [list.as_ref(), index.as_ref()])
+        }
+        Expression::SetIndex(object, index, value) => {
+            out.push_str("(= ");
+            parenthesize(out, "index", [object.as_ref(), index.as_ref()]);
+            out.push(' ');
+            write_ast(out, &value.value);
+            out.push(')');
+        }
+        Expression::Get(object, name) => {
+            out.push_str("(. ");
+            write_ast(out, &object.value);
+            out.push(' ');
+            out.push_str(name.as_str());
+            out.push(')');
+        }
+        Expression::This => out.push_str("this"),
+        Expression::Super(method) => {
+            out.push_str("(super ");
+            out.push_str(method.as_str());
+            out.push(')');
+        }
+    }
+}
+
+// Renders an expression back into Lox source, adding only the parentheses
+// that the default precedence table requires.
+pub fn unparse(expression: &Expression) -> String {
+    let mut unparser = Unparser {
+        out: String::new(),
+        precedence: Precedence::default(),
+    };
+    unparser.expression(expression, COMMA);
+    unparser.out
+}
+
+// Binding strengths, loosest first. Binary levels sit between `AND` and the
+// unary level, one per row of the precedence table, and `**` binds tighter
+// than unary operators.
+const COMMA: usize = 0;
+const ASSIGNMENT: usize = 1;
+const OR: usize = 2;
+const AND: usize = 3;
+const FIRST_LEVEL: usize = 4;
+
+struct Unparser {
+    out: String,
+    precedence: Precedence,
+}
+
+impl Unparser {
+    fn unary(&self) -> usize {
+        FIRST_LEVEL + self.precedence.levels.len()
+    }
+
+    fn power(&self) -> usize {
+        self.unary() + 1
+    }
+
+    fn postfix(&self) -> usize {
+        self.power() + 1
+    }
+
+    fn binding(&self, expression: &Expression) -> usize {
+        match expression {
+            Expression::Comma(_) => COMMA,
+            Expression::Assign(..) | Expression::SetIndex(..) => ASSIGNMENT,
+            Expression::Logical(LogicalOperator::Or, ..) => OR,
+            Expression::Logical(LogicalOperator::And, ..) => AND,
+            Expression::Binary(BinaryOperator::Pow, ..) => self.power(),
+            Expression::Binary(operator, ..) => match self.level(*operator) {
+                Some((level, _)) => FIRST_LEVEL + level,
+                None => FIRST_LEVEL,
+            },
+            Expression::Unary(..) => self.unary(),
+            Expression::Call(..) | Expression::Index(..) | Expression::Get(..) => self.postfix(),
+            Expression::Literal(_)
+            | Expression::Variable(_)
+            | Expression::List(_)
+            | Expression::Map(_)
+            | Expression::This
+            | Expression::Super(_) => self.postfix() + 1,
+        }
+    }
+
+    fn level(&self, operator: BinaryOperator) -> Option<(usize, Associativity)> {
+        self.precedence
+            .levels
+            .iter()
+            .enumerate()
+            .find(|(_, level)| level.operators.iter().any(|(_, op)| *op == operator))
+            .map(|(index, level)| (index, level.associativity))
+    }
+
+    fn expression(&mut self, expression: &Expression, min: usize) {
+        let grouped = self.binding(expression) < min;
+        if grouped {
+            self.out.push('(');
+        }
+        match expression {
+            Expression::Literal(value) => self.literal(value),
+            Expression::Variable(name) => self.out.push_str(name.as_str()),
+            Expression::Assign(name, value) => {
+                self.out.push_str(name.as_str());
+                self.out.push_str(" = ");
+                self.expression(&value.value, ASSIGNMENT);
+            }
+            Expression::Unary(operator, operand) => {
+                self.out.push_str(&operator.to_string());
+                self.expression(&operand.value, self.unary());
+            }
+            // The exponent may be negated, as in `2 ** -1`, but the base may not.
+            Expression::Binary(BinaryOperator::Pow, left, right) => {
+                self.expression(&left.value, self.postfix());
+                self.out.push_str(" ** ");
+                self.expression(&right.value, self.unary());
+            }
+            Expression::Binary(operator, left, right) => {
+                let (level, associativity) =
+                    self.level(*operator).unwrap_or((0, Associativity::Left));
+                let (left_min, right_min) = match associativity {
+                    Associativity::Left => (FIRST_LEVEL + level, FIRST_LEVEL + level + 1),
+                    Associativity::Right => (FIRST_LEVEL + level + 1, FIRST_LEVEL + level),
+                };
+                self.expression(&left.value, left_min);
+                self.out.push_str(&format!(" {} ", operator));
+                self.expression(&right.value, right_min);
+            }
+            Expression::Logical(operator, left, right) => {
+                let level = self.binding(expression);
+                self.expression(&left.value, level);
+                self.out.push_str(&format!(" {} ", operator));
+                self.expression(&right.value, level + 1);
+            }
+            Expression::Call(callee, arguments) => {
+                self.expression(&callee.value, self.postfix());
+                self.out.push('(');
+                self.list(arguments);
+                self.out.push(')');
+            }
+            Expression::Comma(expressions) => self.list(expressions),
+            Expression::List(elements) => {
+                self.out.push('[');
+                self.list(elements);
+                self.out.push(']');
+            }
+            Expression::Map(entries) => {
+                self.out.push('{');
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(&key.value, ASSIGNMENT);
+                    self.out.push_str(": ");
+                    self.expression(&value.value, ASSIGNMENT);
+                }
+                self.out.push('}');
+            }
+            Expression::Index(list, index) => {
+                self.expression(&list.value, self.postfix());
+                self.out.push('[');
+                self.expression(&index.value, COMMA);
+                self.out.push(']');
+            }
+            Expression::SetIndex(object, index, value) => {
+                self.expression(&object.value, self.postfix());
+                self.out.push('[');
+                self.expression(&index.value, COMMA);
+                self.out.push_str("] = ");
+                self.expression(&value.value, ASSIGNMENT);
+            }
+            Expression::Get(object, name) => {
+                // `1.x` would scan as a malformed number.
+                if let Expression::Literal(Value::Number(_)) = object.value {
+                    self.out.push('(');
+                    self.expression(&object.value, COMMA);
+                    self.out.push(')');
+                } else {
+                    self.expression(&object.value, self.postfix());
+                }
+                self.out.push('.');
+                self.out.push_str(name.as_str());
+            }
+            Expression::This => self.out.push_str("this"),
+            Expression::Super(method) => {
+                self.out.push_str("super.");
+                self.out.push_str(method.as_str());
+            }
+        }
+        if grouped {
+            self.out.push(')');
+        }
+    }
+
+    fn list(&mut self, expressions: &[Node]) {
+        for (index, expression) in expressions.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            self.expression(&expression.value, ASSIGNMENT);
+        }
+    }
+
+    fn literal(&mut self, value: &Value) {
+        let Value::String(string) = value else {
+            self.out.push_str(&value.to_string());
+            return;
+        };
+        self.out.push('"');
+        for ch in string.chars() {
+            match ch {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '\r' => self.out.push_str("\\r"),
+                '\0' => self.out.push_str("\\0"),
+                ch => self.out.push(ch),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+fn parenthesize<'a>(out: &mut String, name: &str, expressions: impl IntoIterator<Item = &'a Node>) {
+    out.push('(');
+    out.push_str(name);
+    for expression in expressions {
+        out.push(' ');
+        write_ast(out, &expression.value);
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn parse(source: &str) -> Expression {
+        match Parser::new(source, Scanner::new(source)).next() {
+            Some(statement) => match statement.value {
+                Ok(Statement::Expression(expression)) => expression,
+                statement => panic!("expected an expression, got {:?}", statement),
+            },
+            None => panic!("no statement in {:?}", source),
+        }
+    }
+
+    fn printed(source: &str) -> String {
+        print_ast(&parse(source))
+    }
+
+    #[test]
+    fn print_operators() {
+        assert_eq!(printed("-1 * (2 + 3)"), "(* (- 1) (+ 2 3))");
+        assert_eq!(printed("!true == +1 <= 2"), "(== (! true) (<= (+ 1) 2))");
+        assert_eq!(printed("a or b and c == d"), "(or a (and b (== c d)))");
+        assert_eq!(printed("1 << 2 | 3 & 4 ^ 5"), "(| (<< 1 2) (^ (& 3 4) 5))");
+        assert_eq!(printed("nil != \"text\""), "(!= nil text)");
+    }
+
+    #[test]
+    fn print_other_expressions() {
+        assert_eq!(printed("a = b = 1.5"), "(= a (= b 1.5))");
+        assert_eq!(printed("f(x, g())"), "(call f x (call g))");
+        assert_eq!(printed("a, b"), "(, a b)");
+        assert_eq!(printed("[1, [2]][0]"), "(index (list 1 (list 2)) 0)");
+        assert_eq!(printed("point.x / 2"), "(/ (. point x) 2)");
+        assert_eq!(printed("m[{1: a}] = {}"), "(= (index m (map 1 a)) (map))");
+    }
+
+    #[test]
+    fn unparse_round_trips() {
+        for source in [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "1 - (2 - 3)",
+            "(1 - 2) - 3",
+            "-(1 + 2) / !x",
+            "--1",
+            "a = b = c + 1",
+            "(a = 1) + 2",
+            "f(a, (b, c))(d)[0].e",
+            "(a, b), c",
+            "[1, (2, 3)][f(x) - 1]",
+            "(1).x",
+            "\"say \\\"hi\\\"\\n\" + \"a\\\\b\"",
+            "1 | 2 ^ 3 & (4 << 5) == 6",
+            "(1 == 2) == (3 < 4)",
+            "m[k] = {\"a\": [1], 2: n = 3}[\"a\"]",
+            "a or b and c == d",
+            "(a or b) and !(c and d) or e",
+            "x = a and (b = c)",
+            "-2 ** 2",
+            "(-2) ** 2",
+            "(2 ** 3) ** 2 * 2 ** -f(x)[0]",
+        ] {
+            let expression = parse(source);
+            let unparsed = unparse(&expression);
+            assert_eq!(parse(&unparsed), expression, "{} -> {}", source, unparsed);
+        }
+    }
+
+    #[test]
+    fn unparse_drops_redundant_parentheses() {
+        assert_eq!(unparse(&parse("(1 + (2 * 3))")), "1 + 2 * 3");
+        assert_eq!(unparse(&parse("((1 - 2) - 3)")), "1 - 2 - 3");
+        assert_eq!(unparse(&parse("1 - (2 - 3)")), "1 - (2 - 3)");
+        assert_eq!(unparse(&parse("(-(x))(y)")), "(-x)(y)");
+        assert_eq!(unparse(&parse("-(2 ** (3 ** 2))")), "-2 ** 3 ** 2");
+    }
 }
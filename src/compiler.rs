@@ -0,0 +1,263 @@
+use crate::{
+    ast::{BinaryOperator, Expression, LogicalOperator, Statement, UnaryOperator},
+    intern::Symbol,
+    value::Value,
+    vm::{op, Chunk},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    TooManyConstants,
+    JumpTooLong,
+    TooManyLocals,
+    ReadInOwnInitializer(Symbol),
+    Unsupported,
+}
+
+// A local's depth is `None` until its initializer has been compiled.
+struct Local {
+    name: Symbol,
+    depth: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Compiler {
+    chunk: Chunk,
+    line: u32,
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl Compiler {
+    pub fn set_line(&mut self, line: u32) {
+        self.line = line;
+    }
+
+    pub fn statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::Empty => {}
+            Statement::Expression(expression) => self.expression_statement(expression)?,
+            // A local's value simply stays on the stack, in its slot.
+            Statement::Var(name, initializer) => {
+                if self.scope_depth > 0 {
+                    self.add_local(*name)?;
+                }
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => self.emit(op::NIL),
+                }
+                match self.locals.last_mut() {
+                    Some(local) if self.scope_depth > 0 => local.depth = Some(self.scope_depth),
+                    _ => {
+                        let name = self.identifier_constant(*name)?;
+                        self.emit_all(&[op::DEFINE_GLOBAL, name]);
+                    }
+                }
+            }
+            Statement::Block(statements) => {
+                self.scope_depth += 1;
+                let result = statements
+                    .iter()
+                    .try_for_each(|statement| self.statement(statement));
+                self.end_scope();
+                result?;
+            }
+            // The condition is popped whichever way the jump goes.
+            Statement::While(condition, body) => {
+                let loop_start = self.chunk.code.len();
+                self.expression(condition)?;
+                let exit = self.emit_jump(op::JUMP_IF_FALSE);
+                self.emit(op::POP);
+                self.statement(body)?;
+                self.emit_loop(loop_start)?;
+                self.patch_jump(exit)?;
+                self.emit(op::POP);
+            }
+            _ => return Err(Error::Unsupported),
+        }
+        Ok(())
+    }
+
+    pub fn expression_statement(&mut self, expression: &Expression) -> Result<(), Error> {
+        self.expression(expression)?;
+        self.emit(op::POP);
+        Ok(())
+    }
+
+    pub fn expression(&mut self, expression: &Expression) -> Result<(), Error> {
+        match expression {
+            Expression::Literal(Value::Boolean(true)) => self.emit(op::TRUE),
+            Expression::Literal(Value::Boolean(false)) => self.emit(op::FALSE),
+            Expression::Literal(Value::Nil) => self.emit(op::NIL),
+            Expression::Literal(value) => self.emit_constant(value.clone())?,
+            Expression::Variable(name) => match self.resolve_local(*name)? {
+                Some(slot) => self.emit_all(&[op::GET_LOCAL, slot]),
+                None => {
+                    let name = self.identifier_constant(*name)?;
+                    self.emit_all(&[op::GET_GLOBAL, name]);
+                }
+            },
+            Expression::Assign(name, value) => {
+                self.expression(&value.value)?;
+                match self.resolve_local(*name)? {
+                    Some(slot) => self.emit_all(&[op::SET_LOCAL, slot]),
+                    None => {
+                        let name = self.identifier_constant(*name)?;
+                        self.emit_all(&[op::SET_GLOBAL, name]);
+                    }
+                }
+            }
+            Expression::Call(..)
+            | Expression::List(_)
+            | Expression::Map(_)
+            | Expression::Index(..)
+            | Expression::SetIndex(..)
+            | Expression::Get(..)
+            | Expression::This
+            | Expression::Super(_) => return Err(Error::Unsupported),
+            Expression::Comma(expressions) => {
+                for (index, expression) in expressions.iter().enumerate() {
+                    if index > 0 {
+                        self.emit(op::POP);
+                    }
+                    self.expression(&expression.value)?;
+                }
+            }
+            Expression::Unary(operator, expression) => {
+                self.expression(&expression.value)?;
+                match operator {
+                    UnaryOperator::Neg => self.emit(op::NEGATE),
+                    UnaryOperator::Not => self.emit(op::NOT),
+                    UnaryOperator::Pos => return Err(Error::Unsupported),
+                }
+            }
+            // The left operand stays on the stack as the result when it
+            // decides it, and is popped otherwise.
+            Expression::Logical(LogicalOperator::And, left, right) => {
+                self.expression(&left.value)?;
+                let end = self.emit_jump(op::JUMP_IF_FALSE);
+                self.emit(op::POP);
+                self.expression(&right.value)?;
+                self.patch_jump(end)?;
+            }
+            Expression::Logical(LogicalOperator::Or, left, right) => {
+                self.expression(&left.value)?;
+                let right_operand = self.emit_jump(op::JUMP_IF_FALSE);
+                let end = self.emit_jump(op::JUMP);
+                self.patch_jump(right_operand)?;
+                self.emit(op::POP);
+                self.expression(&right.value)?;
+                self.patch_jump(end)?;
+            }
+            Expression::Binary(operator, left, right) => {
+                self.expression(&left.value)?;
+                self.expression(&right.value)?;
+                match operator {
+                    BinaryOperator::Add => self.emit(op::ADD),
+                    BinaryOperator::Sub => self.emit(op::SUBTRACT),
+                    BinaryOperator::Div => self.emit(op::DIVIDE),
+                    BinaryOperator::Mul => self.emit(op::MULTIPLY),
+                    BinaryOperator::Equal => self.emit(op::EQUAL),
+                    BinaryOperator::NotEqual => self.emit_all(&[op::EQUAL, op::NOT]),
+                    BinaryOperator::Greater => self.emit(op::GREATER),
+                    BinaryOperator::GreaterEqual => self.emit_all(&[op::LESS, op::NOT]),
+                    BinaryOperator::Less => self.emit(op::LESS),
+                    BinaryOperator::LessEqual => self.emit_all(&[op::GREATER, op::NOT]),
+                    BinaryOperator::Pow
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => return Err(Error::Unsupported),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Chunk {
+        self.emit(op::RETURN);
+        self.chunk
+    }
+
+    fn emit(&mut self, byte: u8) {
+        self.chunk.write(byte, self.line);
+    }
+
+    fn emit_all(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.emit(byte);
+        }
+    }
+
+    // Emits a jump with a placeholder distance, returning where the distance
+    // goes so that `patch_jump` can fill it in.
+    pub fn emit_jump(&mut self, instruction: u8) -> usize {
+        self.emit_all(&[instruction, 0xff, 0xff]);
+        self.chunk.code.len() - 2
+    }
+
+    // Points the jump at `offset` to the next instruction emitted.
+    pub fn patch_jump(&mut self, offset: usize) -> Result<(), Error> {
+        let distance = self.chunk.code.len() - offset - 2;
+        let distance = u16::try_from(distance).map_err(|_| Error::JumpTooLong)?;
+        self.chunk.patch(offset, &distance.to_be_bytes());
+        Ok(())
+    }
+
+    // Jumps back to `loop_start`.
+    pub fn emit_loop(&mut self, loop_start: usize) -> Result<(), Error> {
+        self.emit(op::LOOP);
+        let distance = self.chunk.code.len() + 2 - loop_start;
+        let distance = u16::try_from(distance).map_err(|_| Error::JumpTooLong)?;
+        self.emit_all(&distance.to_be_bytes());
+        Ok(())
+    }
+
+    fn add_local(&mut self, name: Symbol) -> Result<(), Error> {
+        if self.locals.len() > u8::MAX as usize {
+            return Err(Error::TooManyLocals);
+        }
+        self.locals.push(Local { name, depth: None });
+        Ok(())
+    }
+
+    // Locals are searched innermost first so that shadowing works, and a
+    // local's slot is its index, since locals sit on the stack in order.
+    fn resolve_local(&self, name: Symbol) -> Result<Option<u8>, Error> {
+        match self.locals.iter().rposition(|local| local.name == name) {
+            Some(slot) if self.locals[slot].depth.is_none() => {
+                Err(Error::ReadInOwnInitializer(name))
+            }
+            Some(slot) => Ok(Some(slot as u8)),
+            None => Ok(None),
+        }
+    }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while let Some(local) = self.locals.last() {
+            if local.depth.is_some_and(|depth| depth <= self.scope_depth) {
+                break;
+            }
+            self.locals.pop();
+            self.emit(op::POP);
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<(), Error> {
+        let constant = self.make_constant(value)?;
+        self.emit_all(&[op::CONSTANT, constant]);
+        Ok(())
+    }
+
+    // Globals are looked up by name, which the VM reads from the constants.
+    fn identifier_constant(&mut self, name: Symbol) -> Result<u8, Error> {
+        self.make_constant(Value::String(name.as_str().to_string()))
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<u8, Error> {
+        let constant = self.chunk.add_constant(value);
+        u8::try_from(constant).map_err(|_| Error::TooManyConstants)
+    }
+}
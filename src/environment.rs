@@ -0,0 +1,88 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{intern::Symbol, value::Value};
+
+// The variables of one scope at some point, for `Environment::restore`.
+pub struct EnvSnapshot {
+    values: HashMap<Symbol, Value>,
+}
+
+#[derive(Default)]
+pub struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        match self.values.get(&name) {
+            Some(value) => Some(value.clone()),
+            None => self
+                .enclosing
+                .as_ref()
+                .and_then(|enclosing| enclosing.borrow().get(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: Symbol, value: Value) -> bool {
+        match self.values.get_mut(&name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => self
+                .enclosing
+                .as_ref()
+                .is_some_and(|enclosing| enclosing.borrow_mut().assign(name, value)),
+        }
+    }
+
+    // Copies this scope's variables, but not enclosing ones. Maps and
+    // functions are shared rather than copied, so changes made through them
+    // aren't undone by `restore`.
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            values: self.values.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: EnvSnapshot) {
+        self.values = snapshot.values;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_and_restore() {
+        let (a, b, c) = (
+            Symbol::intern("a"),
+            Symbol::intern("b"),
+            Symbol::intern("c"),
+        );
+        let mut environment = Environment::default();
+        environment.define(a, Value::Number(1.0));
+        environment.define(b, Value::List(vec![Value::Nil]));
+        let snapshot = environment.snapshot();
+        environment.assign(a, Value::Number(2.0));
+        environment.assign(b, Value::Nil);
+        environment.define(c, Value::Boolean(true));
+        environment.restore(snapshot);
+        assert_eq!(environment.get(a), Some(Value::Number(1.0)));
+        assert_eq!(environment.get(b), Some(Value::List(vec![Value::Nil])));
+        assert_eq!(environment.get(c), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Display},
+    sync::{OnceLock, PoisonError, RwLock},
+};
+
+// An index into the process-wide table of interned strings. Interned strings
+// are leaked so that `as_str` can hand them out as `&'static str`: memory for
+// each distinct identifier is never freed, which matters to long-running
+// embedders that parse untrusted input.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    strings: Vec<&'static str>,
+    symbols: HashMap<&'static str, Symbol>,
+}
+
+impl Interner {
+    fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(string) {
+            return symbol;
+        }
+        let string: &'static str = Box::leak(Box::from(string));
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(string);
+        self.symbols.insert(string, symbol);
+        symbol
+    }
+
+    fn resolve(&self, symbol: Symbol) -> &'static str {
+        self.strings[symbol.0 as usize]
+    }
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(RwLock::default)
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Self {
+        // Most identifiers repeat, and those only need the shared lock.
+        let symbol = interner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .symbols
+            .get(string)
+            .copied();
+        symbol.unwrap_or_else(|| {
+            interner()
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .intern(string)
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .resolve(self)
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_string_same_symbol() {
+        let mut interner = Interner::default();
+        let first = interner.intern("counter");
+        let second = interner.intern(&String::from("counter"));
+        let other = interner.intern("count");
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(std::ptr::eq(
+            interner.resolve(first),
+            interner.resolve(second)
+        ));
+        assert_eq!(interner.resolve(other), "count");
+    }
+
+    #[test]
+    fn global_symbols() {
+        let symbol = Symbol::intern("global_symbols");
+        assert_eq!(symbol, Symbol::intern("global_symbols"));
+        assert_eq!(symbol.as_str(), "global_symbols");
+        assert_eq!(
+            format!("{} {:?}", symbol, symbol),
+            "global_symbols \"global_symbols\""
+        );
+    }
+}
@@ -1,79 +1,832 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display},
+    io::{self, Write},
+    iter,
+    rc::Rc,
+};
+
 use crate::{
-    ast::{BinaryOperator, Expression, UnaryOperator},
-    value::{Number, Type, TypeError, Value, Variant},
+    ast::{BinaryOperator, Expression, LogicalOperator, Node, Statement, UnaryOperator},
+    environment::Environment,
+    intern::Symbol,
+    native,
+    parser::{self, Parser},
+    scanner::Scanner,
+    span::{Span, Spanned},
+    value::{AsIndex, Key, MulError, Number, Type, TypeError, UserFunction, Value, Variant},
+    Error,
 };
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operand {
+    Left,
+    Right,
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operand::Left => "left",
+            Operand::Right => "right",
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum RuntimeError {
     TypeError(TypeError),
+    Operand(BinaryOperator, Operand, TypeError),
+    MismatchedTypes(Type, Type),
+    UndefinedVariable(Symbol),
+    NotCallable(Value),
+    NoProperties(Value),
+    Arity { expected: usize, got: usize },
+    NotAnInteger(BinaryOperator, Operand, Number),
+    IndexOutOfBounds { index: Number, len: usize },
+    InvalidIndex(Number),
+    RepeatCount(Number),
+    InvalidKey(Value),
+    StackOverflow(usize),
+    // Classes don't exist yet, so `this` and `super` never have one.
+    OutsideClass(&'static str),
+    Io(io::Error),
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::TypeError(error) => write!(f, "{}", error),
+            RuntimeError::Operand(operator, operand, error) => {
+                write!(f, "{} operand of '{}': {}", operand, operator, error)
+            }
+            RuntimeError::MismatchedTypes(left, right) => {
+                write!(f, "can't compare {} with {}", left, right)
+            }
+            RuntimeError::UndefinedVariable(name) => write!(f, "undefined variable '{}'", name),
+            RuntimeError::NotCallable(value) => {
+                write!(f, "can only call functions, got {}", value.ty())
+            }
+            RuntimeError::NoProperties(value) => {
+                write!(f, "{} values have no properties", value.ty())
+            }
+            RuntimeError::Arity { expected, got } => {
+                write!(f, "expected {} arguments but got {}", expected, got)
+            }
+            RuntimeError::NotAnInteger(operator, operand, number) => write!(
+                f,
+                "{} operand of '{}': expected an integer but got {}",
+                operand, operator, number
+            ),
+            RuntimeError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} out of bounds for length {}", index, len)
+            }
+            RuntimeError::RepeatCount(count) => {
+                write!(f, "can't repeat a string {} times", count)
+            }
+            RuntimeError::InvalidIndex(index) => {
+                write!(f, "expected an integer index but got {}", index)
+            }
+            RuntimeError::InvalidKey(value) => write!(f, "{} can't be used as a key", value),
+            RuntimeError::StackOverflow(depth) => {
+                write!(f, "stack overflow: more than {} nested calls", depth)
+            }
+            RuntimeError::OutsideClass(keyword) => {
+                write!(f, "can't use '{}' outside of a class", keyword)
+            }
+            RuntimeError::Io(error) => write!(f, "can't write output: {}", error),
+        }
+    }
 }
 
+impl std::error::Error for RuntimeError {}
+
 impl From<TypeError> for RuntimeError {
     fn from(error: TypeError) -> Self {
         Self::TypeError(error)
     }
 }
 
-pub fn eval(expression: Expression) -> Result<Value, RuntimeError> {
-    match expression {
-        Expression::Literal(value) => Ok(value),
-        Expression::Grouping(expression) => eval(*expression),
-        Expression::Unary(operator, expression) => {
-            let value = eval(*expression)?;
-            match operator {
-                UnaryOperator::Neg => eval_unary(value, |v: Number| -v),
-                UnaryOperator::Not => Ok(Value::Boolean(!is_truthy(value))),
-            }
-        }
-        Expression::Binary(operator, left, right) => {
-            let left = eval(*left)?;
-            let right = eval(*right)?;
-            match operator {
-                BinaryOperator::Add => match (left, right) {
-                    (Value::String(left), Value::String(right)) => Ok(Value::String(left + &right)),
-                    (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
-                    (Value::String(_), right) => Err(RuntimeError::TypeError(TypeError {
-                        expected: &[Type::String],
-                        actual: right,
-                    })),
-                    (Value::Number(_), right) => Err(RuntimeError::TypeError(TypeError {
-                        expected: &[Type::Number],
-                        actual: right,
-                    })),
-                    (left, _) => Err(RuntimeError::TypeError(TypeError {
-                        expected: &[Type::Number, Type::String],
-                        actual: left,
-                    })),
+// Stops runaway recursion well before ordinary programs get anywhere near it.
+const DEFAULT_MAX_CALL_DEPTH: usize = 5000;
+
+pub struct Interpreter {
+    pub strict_equality: bool,
+    pub coerce_strings: bool,
+    // Lets `run_program` go on to the next statement after an error.
+    pub continue_on_error: bool,
+    // Significant digits `print` shows for numbers, or as many as it takes.
+    pub precision: Option<usize>,
+    // How deeply Lox functions may nest their calls, `DEFAULT_MAX_CALL_DEPTH`
+    // unless changed. Each call recurses in Rust as well, taking a few KiB of
+    // native stack, so running to the default needs a thread of 64 MiB or so.
+    pub max_call_depth: usize,
+    call_depth: usize,
+    environment: Rc<RefCell<Environment>>,
+    output: Box<dyn Write>,
+    // Where the last runtime error happened, if narrower than its statement.
+    error_span: Option<Span>,
+    // Statements `load`ed but not yet run by `step`.
+    pending: VecDeque<Spanned<Result<Statement, parser::Error>>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let mut globals = Environment::default();
+        native::define_globals(&mut globals);
+        Self::with_environment(Rc::new(RefCell::new(globals)))
+    }
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+// Each task keeps the spans it would blame on failure. The top-level
+// expression of a statement has no span of its own.
+#[derive(Copy, Clone)]
+enum Task<'a> {
+    Eval(&'a Expression, Option<Span>),
+    Assign(Symbol, Option<Span>),
+    Call {
+        count: usize,
+        callee: Span,
+        span: Option<Span>,
+    },
+    Comma(usize),
+    List(usize),
+    Map(usize, Option<Span>),
+    Index(Option<Span>),
+    SetIndex(Option<Span>),
+    Get(Span),
+    Unary(UnaryOperator, Span),
+    // Decides from the left operand whether the right one is needed.
+    Logical(LogicalOperator, &'a Node),
+    Binary {
+        operator: BinaryOperator,
+        span: Option<Span>,
+        left: Span,
+        right: Span,
+    },
+}
+
+impl<'a> Task<'a> {
+    fn node(node: &'a Node) -> Self {
+        Task::Eval(&node.value, Some(node.span))
+    }
+
+    fn blame(self, error: &RuntimeError) -> Option<Span> {
+        match self {
+            Task::Eval(_, span)
+            | Task::Assign(_, span)
+            | Task::Map(_, span)
+            | Task::Index(span)
+            | Task::SetIndex(span) => span,
+            Task::Call { callee, .. } if matches!(error, RuntimeError::NotCallable(_)) => {
+                Some(callee)
+            }
+            Task::Call { span, .. } => span,
+            Task::Get(object) => Some(object),
+            Task::Unary(_, operand) => Some(operand),
+            Task::Binary {
+                span, left, right, ..
+            } => match error {
+                RuntimeError::Operand(_, Operand::Left, _)
+                | RuntimeError::NotAnInteger(_, Operand::Left, _) => Some(left),
+                RuntimeError::Operand(_, Operand::Right, _)
+                | RuntimeError::NotAnInteger(_, Operand::Right, _) => Some(right),
+                _ => span,
+            },
+            Task::Comma(_) | Task::List(_) | Task::Logical(..) => None,
+        }
+    }
+}
+
+fn pop(values: &mut Vec<Value>) -> Value {
+    values.pop().expect("operand stack underflow")
+}
+
+impl Interpreter {
+    pub fn with_environment(environment: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            strict_equality: false,
+            coerce_strings: false,
+            continue_on_error: false,
+            precision: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: 0,
+            environment,
+            output: Box::new(io::stdout()),
+            error_span: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        Self {
+            output: Box::new(output),
+            ..Self::default()
+        }
+    }
+
+    // Runs every statement in `source` against this interpreter's state and
+    // returns the values of the expression statements, stopping at the first
+    // error.
+    pub fn interpret(&mut self, source: &str) -> Result<Vec<Value>, Spanned<Error>> {
+        self.run_program(Parser::new(source, Scanner::new(source)))
+            .collect()
+    }
+
+    // Like `interpret`, but carries on past errors and returns every one of
+    // them alongside the values of the statements that succeeded.
+    pub fn interpret_all(&mut self, source: &str) -> (Vec<Value>, Vec<Spanned<Error>>) {
+        let continue_on_error = self.continue_on_error;
+        self.continue_on_error = true;
+        let mut values = Vec::new();
+        let mut errors = Vec::new();
+        for result in self.run_program(Parser::new(source, Scanner::new(source))) {
+            match result {
+                Ok(value) => values.push(value),
+                Err(error) => errors.push(error),
+            }
+        }
+        self.continue_on_error = continue_on_error;
+        (values, errors)
+    }
+
+    // Queues the statements in `source` for `step` to run one at a time.
+    pub fn load(&mut self, source: &str) {
+        self.pending
+            .extend(Parser::new(source, Scanner::new(source)));
+    }
+
+    // Runs the next `load`ed statement, returning its value if it's an
+    // expression statement, or `None` once there are none left. Between
+    // steps the host can inspect the environment.
+    pub fn step(&mut self) -> Option<Result<Option<Value>, Spanned<Error>>> {
+        let statement = self.pending.pop_front()?;
+        Some(self.run_statement(statement))
+    }
+
+    // Runs statements as they are pulled from `statements`, yielding the value
+    // of each expression statement and each error. Stops after the first error
+    // unless `continue_on_error` is set; failing to write output always stops.
+    pub fn run_program<'a, I>(
+        &'a mut self,
+        statements: I,
+    ) -> impl Iterator<Item = Result<Value, Spanned<Error>>> + 'a
+    where
+        I: IntoIterator<Item = Spanned<Result<Statement, parser::Error>>>,
+        I::IntoIter: 'a,
+    {
+        let mut statements = statements.into_iter();
+        let mut stopped = false;
+        iter::from_fn(move || {
+            while !stopped {
+                let result = statements.next()?;
+                match self.run_statement(result) {
+                    Ok(Some(value)) => return Some(Ok(value)),
+                    Ok(None) => {}
+                    Err(error) => {
+                        stopped = !self.continue_on_error
+                            || matches!(error.value, Error::Runtime(RuntimeError::Io(_)));
+                        return Some(Err(error));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    fn run_statement(
+        &mut self,
+        result: Spanned<Result<Statement, parser::Error>>,
+    ) -> Result<Option<Value>, Spanned<Error>> {
+        self.error_span = None;
+        result
+            .value
+            .map_err(Error::Parse)
+            .and_then(|statement| self.execute(statement).map_err(Error::Runtime))
+            .map_err(|error| Spanned {
+                span: match error {
+                    Error::Runtime(_) => self.error_span.take().unwrap_or(result.span),
+                    Error::Parse(ref error) => error.span().unwrap_or(result.span),
                 },
-                BinaryOperator::Sub => eval_binary(left, right, |a: Number, b: Number| a - b),
-                BinaryOperator::Div => eval_binary(left, right, |a: Number, b: Number| a / b),
-                BinaryOperator::Mul => eval_binary(left, right, |a: Number, b: Number| a * b),
-                BinaryOperator::Equal => Ok(Value::Boolean(left == right)),
-                BinaryOperator::NotEqual => Ok(Value::Boolean(left != right)),
-                BinaryOperator::Greater => eval_binary(left, right, |a: Number, b: Number| a > b),
-                BinaryOperator::GreaterEqual => {
-                    eval_binary(left, right, |a: Number, b: Number| a >= b)
+                value: error,
+            })
+    }
+
+    pub fn execute(&mut self, statement: Statement) -> Result<Option<Value>, RuntimeError> {
+        match statement {
+            Statement::Expression(expression) => self.eval(&expression).map(Some),
+            statement => self.run(&statement).map(|_| None),
+        }
+    }
+
+    fn run(&mut self, statement: &Statement) -> Result<Flow, RuntimeError> {
+        match statement {
+            Statement::Empty => {}
+            Statement::Expression(expression) => {
+                self.eval(expression)?;
+            }
+            Statement::Print(expression) => {
+                let value = self.eval(expression)?;
+                match self.precision {
+                    Some(precision) => writeln!(self.output, "{:.*}", precision, value),
+                    None => writeln!(self.output, "{}", value),
                 }
-                BinaryOperator::Less => eval_binary(left, right, |a: Number, b: Number| a < b),
-                BinaryOperator::LessEqual => {
-                    eval_binary(left, right, |a: Number, b: Number| a <= b)
+                .map_err(RuntimeError::Io)?;
+            }
+            Statement::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(initializer) => self.eval(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(*name, value);
+            }
+            Statement::Block(statements) => {
+                let environment = Environment::new(self.environment.clone());
+                return self.run_block(statements, Rc::new(RefCell::new(environment)));
+            }
+            Statement::If(condition, then_branch, else_branch) => {
+                if self.eval(condition)?.is_truthy() {
+                    return self.run(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.run(else_branch);
+                }
+            }
+            Statement::While(condition, body) => {
+                while self.eval(condition)?.is_truthy() {
+                    match self.run(body)? {
+                        Flow::Break => break,
+                        Flow::Normal | Flow::Continue => {}
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+            Statement::For(initializer, condition, increment, body) => {
+                let environment = Environment::new(self.environment.clone());
+                let previous =
+                    std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+                let result = self.run_for(initializer, condition, increment, body);
+                self.environment = previous;
+                return result;
+            }
+            Statement::Break => return Ok(Flow::Break),
+            Statement::Continue => return Ok(Flow::Continue),
+            Statement::Function(declaration) => {
+                let function = UserFunction {
+                    declaration: declaration.clone(),
+                    closure: self.environment.clone(),
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(declaration.name, Value::Function(Rc::new(function)));
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_for(
+        &mut self,
+        initializer: &Option<Box<Statement>>,
+        condition: &Option<Expression>,
+        increment: &Option<Expression>,
+        body: &Statement,
+    ) -> Result<Flow, RuntimeError> {
+        if let Some(initializer) = initializer {
+            self.run(initializer)?;
+        }
+        loop {
+            if let Some(condition) = condition {
+                if !self.eval(condition)?.is_truthy() {
+                    break;
+                }
+            }
+            match self.run(body)? {
+                Flow::Break => break,
+                Flow::Normal | Flow::Continue => {}
+                flow @ Flow::Return(_) => return Ok(flow),
+            }
+            if let Some(increment) = increment {
+                self.eval(increment)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_block(
+        &mut self,
+        statements: &[Statement],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Flow, RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let mut result = Ok(Flow::Normal);
+        for statement in statements {
+            result = self.run(statement);
+            if !matches!(result, Ok(Flow::Normal)) {
+                break;
+            }
+        }
+        self.environment = previous;
+        result
+    }
+
+    pub fn eval(&mut self, expression: &Expression) -> Result<Value, RuntimeError> {
+        // Operands are evaluated off an explicit work stack so that deeply
+        // nested expressions don't overflow the native stack.
+        let mut tasks = vec![Task::Eval(expression, None)];
+        let mut values = Vec::new();
+        while let Some(task) = tasks.pop() {
+            let result = match task {
+                Task::Eval(expression, span) => match expression {
+                    Expression::Literal(value) => Ok(value.clone()),
+                    Expression::Variable(name) => {
+                        let value = self.environment.borrow().get(*name);
+                        value.ok_or(RuntimeError::UndefinedVariable(*name))
+                    }
+                    Expression::Assign(name, value) => {
+                        tasks.push(Task::Assign(*name, span));
+                        tasks.push(Task::node(value));
+                        continue;
+                    }
+                    Expression::Call(callee, arguments) => {
+                        tasks.push(Task::Call {
+                            count: arguments.len(),
+                            callee: callee.span,
+                            span,
+                        });
+                        tasks.extend(arguments.iter().rev().map(Task::node));
+                        tasks.push(Task::node(callee));
+                        continue;
+                    }
+                    Expression::Comma(expressions) => {
+                        tasks.push(Task::Comma(expressions.len()));
+                        tasks.extend(expressions.iter().rev().map(Task::node));
+                        continue;
+                    }
+                    Expression::List(elements) => {
+                        tasks.push(Task::List(elements.len()));
+                        tasks.extend(elements.iter().rev().map(Task::node));
+                        continue;
+                    }
+                    Expression::Map(entries) => {
+                        tasks.push(Task::Map(entries.len(), span));
+                        for (key, value) in entries.iter().rev() {
+                            tasks.push(Task::node(value));
+                            tasks.push(Task::node(key));
+                        }
+                        continue;
+                    }
+                    Expression::SetIndex(object, index, value) => {
+                        tasks.push(Task::SetIndex(span));
+                        tasks.push(Task::node(value));
+                        tasks.push(Task::node(index));
+                        tasks.push(Task::node(object));
+                        continue;
+                    }
+                    Expression::This => Err(RuntimeError::OutsideClass("this")),
+                    Expression::Super(_) => Err(RuntimeError::OutsideClass("super")),
+                    Expression::Get(object, _) => {
+                        tasks.push(Task::Get(object.span));
+                        tasks.push(Task::node(object));
+                        continue;
+                    }
+                    Expression::Index(list, index) => {
+                        tasks.push(Task::Index(span));
+                        tasks.push(Task::node(index));
+                        tasks.push(Task::node(list));
+                        continue;
+                    }
+                    Expression::Unary(operator, operand) => {
+                        tasks.push(Task::Unary(*operator, operand.span));
+                        tasks.push(Task::node(operand));
+                        continue;
+                    }
+                    Expression::Logical(operator, left, right) => {
+                        tasks.push(Task::Logical(*operator, right));
+                        tasks.push(Task::node(left));
+                        continue;
+                    }
+                    Expression::Binary(operator, left, right) => {
+                        tasks.push(Task::Binary {
+                            operator: *operator,
+                            span,
+                            left: left.span,
+                            right: right.span,
+                        });
+                        tasks.push(Task::node(right));
+                        tasks.push(Task::node(left));
+                        continue;
+                    }
+                },
+                Task::Assign(name, _) => {
+                    let value = pop(&mut values);
+                    if self.environment.borrow_mut().assign(name, value.clone()) {
+                        Ok(value)
+                    } else {
+                        Err(RuntimeError::UndefinedVariable(name))
+                    }
+                }
+                Task::Call { count, .. } => {
+                    let arguments = values.split_off(values.len() - count);
+                    let callee = pop(&mut values);
+                    self.call(callee, arguments)
+                }
+                Task::Comma(count) => {
+                    let value = pop(&mut values);
+                    values.truncate(values.len() + 1 - count);
+                    Ok(value)
+                }
+                Task::List(count) => Ok(Value::List(values.split_off(values.len() - count))),
+                Task::Map(count, _) => {
+                    let entries = values.split_off(values.len() - 2 * count);
+                    map(entries)
+                }
+                Task::SetIndex(_) => {
+                    let value = pop(&mut values);
+                    let index = pop(&mut values);
+                    let object = pop(&mut values);
+                    set_index(object, index, value)
+                }
+                Task::Index(_) => {
+                    let index = pop(&mut values);
+                    let value = pop(&mut values);
+                    index_value(value, index)
+                }
+                // Nothing has properties until there are objects.
+                Task::Get(_) => Err(RuntimeError::NoProperties(pop(&mut values))),
+                Task::Unary(operator, _) => {
+                    let value = pop(&mut values);
+                    match operator {
+                        UnaryOperator::Neg => eval_unary(value, |v: Number| -v),
+                        UnaryOperator::Not => Ok(Value::Boolean(!value.is_truthy())),
+                        UnaryOperator::Pos => eval_unary(value, |v: Number| v),
+                    }
+                }
+                Task::Logical(operator, right) => {
+                    let left = pop(&mut values);
+                    let decided = match operator {
+                        LogicalOperator::And => !left.is_truthy(),
+                        LogicalOperator::Or => left.is_truthy(),
+                    };
+                    if !decided {
+                        tasks.push(Task::node(right));
+                        continue;
+                    }
+                    Ok(left)
+                }
+                Task::Binary { operator, .. } => {
+                    let right = pop(&mut values);
+                    let left = pop(&mut values);
+                    self.eval_binary(operator, left, right)
+                }
+            };
+            match result {
+                Ok(value) => values.push(value),
+                Err(error) => {
+                    self.error_span = task.blame(&error);
+                    return Err(error);
                 }
             }
         }
+        Ok(pop(&mut values))
+    }
+
+    fn eval_binary(
+        &self,
+        operator: BinaryOperator,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, RuntimeError> {
+        match operator {
+            BinaryOperator::Add => match (left, right) {
+                (left @ Value::String(_), right) | (left, right @ Value::String(_))
+                    if self.coerce_strings =>
+                {
+                    Ok(Value::String(format!("{}{}", left, right)))
+                }
+                (left, right) => {
+                    let operand = faulty_operand(&left);
+                    (left + right).map_err(|error| RuntimeError::Operand(operator, operand, error))
+                }
+            },
+            BinaryOperator::Sub => eval_binary(operator, left, right, |a: Number, b: Number| a - b),
+            BinaryOperator::Div => eval_binary(operator, left, right, |a: Number, b: Number| a / b),
+            BinaryOperator::Mul => {
+                let operand = faulty_operand(&left);
+                (left * right).map_err(|error| match error {
+                    MulError::Type(error) => RuntimeError::Operand(operator, operand, error),
+                    MulError::RepeatCount(count) => RuntimeError::RepeatCount(count),
+                })
+            }
+            BinaryOperator::Pow => eval_binary(operator, left, right, Number::powf),
+            BinaryOperator::Equal => Ok(Value::Boolean(self.is_equal(left, right)?)),
+            BinaryOperator::NotEqual => Ok(Value::Boolean(!self.is_equal(left, right)?)),
+            BinaryOperator::Greater => {
+                eval_binary(operator, left, right, |a: Number, b: Number| a > b)
+            }
+            BinaryOperator::GreaterEqual => {
+                eval_binary(operator, left, right, |a: Number, b: Number| a >= b)
+            }
+            BinaryOperator::Less => {
+                eval_binary(operator, left, right, |a: Number, b: Number| a < b)
+            }
+            BinaryOperator::LessEqual => {
+                eval_binary(operator, left, right, |a: Number, b: Number| a <= b)
+            }
+            BinaryOperator::BitAnd => eval_bitwise(operator, left, right, |a, b| a & b),
+            BinaryOperator::BitOr => eval_bitwise(operator, left, right, |a, b| a | b),
+            BinaryOperator::BitXor => eval_bitwise(operator, left, right, |a, b| a ^ b),
+            // Shift counts wrap modulo 64 rather than erroring.
+            BinaryOperator::ShiftLeft => {
+                eval_bitwise(operator, left, right, |a, b| a.wrapping_shl(b as u32))
+            }
+            BinaryOperator::ShiftRight => {
+                eval_bitwise(operator, left, right, |a, b| a.wrapping_shr(b as u32))
+            }
+        }
+    }
+
+    fn call(&mut self, callee: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::NativeFn(native) => {
+                check_arity(native.arity, arguments.len())?;
+                (native.function)(&arguments)
+            }
+            Value::Function(function) => {
+                let declaration = &function.declaration;
+                check_arity(declaration.params.len(), arguments.len())?;
+                if self.call_depth == self.max_call_depth {
+                    return Err(RuntimeError::StackOverflow(self.max_call_depth));
+                }
+                let mut environment = Environment::new(function.closure.clone());
+                for (param, argument) in declaration.params.iter().zip(arguments) {
+                    environment.define(*param, argument);
+                }
+                self.call_depth += 1;
+                let flow = self.run_block(&declaration.body, Rc::new(RefCell::new(environment)));
+                self.call_depth -= 1;
+                match flow? {
+                    Flow::Return(value) => Ok(value),
+                    Flow::Normal | Flow::Break | Flow::Continue => Ok(Value::Nil),
+                }
+            }
+            callee => Err(RuntimeError::NotCallable(callee)),
+        }
+    }
+
+    // Any two values can be compared for equality, and values of different
+    // types are never equal. Only `strict_equality` makes this fail.
+    fn is_equal(&self, left: Value, right: Value) -> Result<bool, RuntimeError> {
+        let (left_type, right_type) = (left.ty(), right.ty());
+        if self.strict_equality
+            && left_type != right_type
+            && left_type != Type::Nil
+            && right_type != Type::Nil
+        {
+            return Err(RuntimeError::MismatchedTypes(left_type, right_type));
+        }
+        Ok(left == right)
     }
 }
 
-fn is_truthy(value: Value) -> bool {
-    !matches!(value, Value::Boolean(false) | Value::Nil)
+pub fn eval(expression: Expression) -> Result<Value, RuntimeError> {
+    Interpreter::default().eval(&expression)
 }
 
-fn eval_binary<A, B, F>(left: Value, right: Value, f: F) -> Result<Value, RuntimeError>
+// Evaluates `expression` to a Rust value, e.g. `let n: f64 = eval_as(expression)?`.
+pub fn eval_as<T: Variant>(expression: Expression) -> Result<T, RuntimeError> {
+    Ok(T::from_value(eval(expression)?)?)
+}
+
+fn check_arity(expected: usize, got: usize) -> Result<(), RuntimeError> {
+    if expected != got {
+        return Err(RuntimeError::Arity { expected, got });
+    }
+    Ok(())
+}
+
+fn eval_binary<A, B, F>(
+    operator: BinaryOperator,
+    left: Value,
+    right: Value,
+    f: F,
+) -> Result<Value, RuntimeError>
 where
     A: Variant,
     B: Variant,
     F: Fn(A, A) -> B,
 {
-    Ok(f(A::from_value(left)?, A::from_value(right)?).into_value())
+    let operand = |value, operand| {
+        A::from_value(value).map_err(|error| RuntimeError::Operand(operator, operand, error))
+    };
+    let left = operand(left, Operand::Left)?;
+    let right = operand(right, Operand::Right)?;
+    Ok(f(left, right).into_value())
+}
+
+// Strings are indexed by character rather than by byte.
+// Reading a missing key gives `nil`, like reading a field that was never set.
+fn index_value(value: Value, index: Value) -> Result<Value, RuntimeError> {
+    if let Value::Map(entries) = &value {
+        let key = Key::new(index)?;
+        return Ok(entries.borrow().get(&key).cloned().unwrap_or(Value::Nil));
+    }
+    let index = Number::from_value(index)?;
+    match value {
+        Value::List(mut values) => {
+            let position = position(index, values.len())?;
+            Ok(values.swap_remove(position))
+        }
+        Value::String(string) => {
+            let position = position(index, string.chars().count())?;
+            let ch = string.chars().nth(position).unwrap_or_default();
+            Ok(Value::String(String::from(ch)))
+        }
+        value => Err(RuntimeError::TypeError(TypeError {
+            expected: &[Type::List, Type::String, Type::Map],
+            actual: value,
+        })),
+    }
+}
+
+fn map(entries: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut map = HashMap::with_capacity(entries.len() / 2);
+    let mut entries = entries.into_iter();
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        map.insert(Key::new(key)?, value);
+    }
+    Ok(Value::Map(Rc::new(RefCell::new(map))))
+}
+
+fn set_index(object: Value, index: Value, value: Value) -> Result<Value, RuntimeError> {
+    let Value::Map(entries) = object else {
+        return Err(RuntimeError::TypeError(TypeError {
+            expected: &[Type::Map],
+            actual: object,
+        }));
+    };
+    entries.borrow_mut().insert(Key::new(index)?, value.clone());
+    Ok(value)
+}
+
+// `+` and `*` accept a number or a string on the left, and when they do the
+// right operand is the one with the wrong type.
+fn faulty_operand(left: &Value) -> Operand {
+    match left {
+        Value::Number(_) | Value::String(_) => Operand::Right,
+        _ => Operand::Left,
+    }
+}
+
+// Negative indices count back from the end.
+fn position(index: Number, len: usize) -> Result<usize, RuntimeError> {
+    let position = if index < 0.0 {
+        len as Number + index
+    } else {
+        index
+    };
+    match position.as_index() {
+        Ok(position) if position < len => Ok(position),
+        _ if position.fract() == 0.0 => Err(RuntimeError::IndexOutOfBounds { index, len }),
+        _ => Err(RuntimeError::InvalidIndex(index)),
+    }
+}
+
+fn eval_bitwise<F>(
+    operator: BinaryOperator,
+    left: Value,
+    right: Value,
+    f: F,
+) -> Result<Value, RuntimeError>
+where
+    F: Fn(i64, i64) -> i64,
+{
+    let integer = |value: Value, operand: Operand| {
+        let number = Number::from_value(value)
+            .map_err(|error| RuntimeError::Operand(operator, operand, error))?;
+        // `as` would saturate out-of-range numbers; 2^63 itself is too big.
+        let range = i64::MIN as Number..-(i64::MIN as Number);
+        if number.fract() != 0.0 || !range.contains(&number) {
+            return Err(RuntimeError::NotAnInteger(operator, operand, number));
+        }
+        Ok(number as i64)
+    };
+    let left = integer(left, Operand::Left)?;
+    let right = integer(right, Operand::Right)?;
+    Ok(Value::Number(f(left, right) as Number))
 }
 
 fn eval_unary<A, B, F>(value: Value, f: F) -> Result<Value, RuntimeError>
@@ -84,3 +837,984 @@ where
 {
     Ok(f(A::from_value(value)?).into_value())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpret,
+        parser::Parser,
+        scanner::Scanner,
+        span::{Span, Spanned},
+        Error,
+    };
+
+    fn parse(source: &str) -> Expression {
+        match Parser::new(source, Scanner::new(source))
+            .next()
+            .unwrap()
+            .value
+            .unwrap()
+        {
+            Statement::Expression(expression) => expression,
+            statement => panic!("expected an expression, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn typed_results() {
+        let number: Number = eval_as(parse("1 + 2 * 3")).unwrap();
+        assert_eq!(number, 7.0);
+        assert!(eval_as::<bool>(parse("1 < 2 and !false")).unwrap());
+        assert_eq!(eval_as::<String>(parse("\"a\" + \"b\"")).unwrap(), "ab");
+        assert!(matches!(
+            eval_as::<Number>(parse("1 < 2")),
+            Err(RuntimeError::TypeError(TypeError {
+                expected: [Type::Number],
+                actual: Value::Boolean(true),
+            }))
+        ));
+        // Evaluation errors come through unchanged.
+        assert!(matches!(
+            eval_as::<bool>(parse("-nil")),
+            Err(RuntimeError::TypeError(TypeError {
+                actual: Value::Nil,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn permissive_equality() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(
+            interpreter.eval(&parse("1 == \"1\"")).unwrap(),
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            interpreter.eval(&parse("1 != \"1\"")).unwrap(),
+            Value::Boolean(true)
+        );
+    }
+
+    #[test]
+    fn step_through_statements() {
+        let environment = Rc::new(RefCell::new(Environment::default()));
+        let mut interpreter = Interpreter::with_environment(environment.clone());
+        interpreter.load("var a = 1; a = a + 1; a * 10;");
+        let a = || environment.borrow().get(Symbol::intern("a"));
+        assert_eq!(a(), None);
+        assert!(matches!(interpreter.step(), Some(Ok(None))));
+        assert_eq!(a(), Some(Value::Number(1.0)));
+        assert!(matches!(interpreter.step(), Some(Ok(Some(_)))));
+        assert_eq!(a(), Some(Value::Number(2.0)));
+        assert!(matches!(
+            interpreter.step(),
+            Some(Ok(Some(Value::Number(n)))) if n == 20.0
+        ));
+        assert_eq!(a(), Some(Value::Number(2.0)));
+        assert!(interpreter.step().is_none());
+        // Errors don't stop later statements from being stepped through.
+        interpreter.load("b; a = 3;");
+        assert!(matches!(
+            interpreter.step(),
+            Some(Err(Spanned {
+                value: Error::Runtime(RuntimeError::UndefinedVariable(_)),
+                ..
+            }))
+        ));
+        assert!(matches!(interpreter.step(), Some(Ok(Some(_)))));
+        assert_eq!(a(), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn equality_across_types() {
+        let samples = ["\"a\"", "1", "true", "clock", "f", "[1]", "m", "nil"];
+        let mut interpreter = Interpreter::default();
+        interpreter.interpret("fun f() {} var m = {1: 2};").unwrap();
+        for (i, left) in samples.iter().enumerate() {
+            for (j, right) in samples.iter().enumerate() {
+                let source = format!("{0} == {1}; {0} != {1};", left, right);
+                assert_eq!(
+                    interpreter.interpret(&source).unwrap(),
+                    [Value::Boolean(i == j), Value::Boolean(i != j)],
+                    "{}",
+                    source
+                );
+            }
+        }
+        // Ordering, unlike equality, is only defined for numbers.
+        for source in ["1 > nil", "nil < 1", "\"a\" <= \"b\"", "true >= false"] {
+            assert!(
+                matches!(
+                    interpreter.eval(&parse(source)),
+                    Err(RuntimeError::Operand(..))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn nan_equality() {
+        let values = interpret(
+            "var nan = 0 / 0; nan == nan; nan != nan; 0 == -0; nil == nil; nil == false; 1 == \"1\";",
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            [false, true, true, true, false, false].map(Value::Boolean)
+        );
+    }
+
+    #[test]
+    fn strict_equality() {
+        let mut interpreter = Interpreter {
+            strict_equality: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            interpreter.eval(&parse("1 == \"1\"")),
+            Err(RuntimeError::MismatchedTypes(Type::Number, Type::String))
+        ));
+        assert!(matches!(
+            interpreter.eval(&parse("true != 1")),
+            Err(RuntimeError::MismatchedTypes(Type::Boolean, Type::Number))
+        ));
+        assert_eq!(
+            interpreter.eval(&parse("1 == nil")).unwrap(),
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            interpreter.eval(&parse("nil != \"a\"")).unwrap(),
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            interpreter.eval(&parse("1 == 1")).unwrap(),
+            Value::Boolean(true)
+        );
+    }
+
+    #[test]
+    fn call_clock() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&parse("clock()")),
+            Ok(Value::Number(_))
+        ));
+    }
+
+    #[test]
+    fn call_with_wrong_arity() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&parse("clock(1)")),
+            Err(RuntimeError::Arity {
+                expected: 0,
+                got: 1
+            })
+        ));
+        assert!(matches!(
+            interpreter.eval(&parse("clock(1, 2)")),
+            Err(RuntimeError::Arity {
+                expected: 0,
+                got: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn call_non_callable() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&parse("1()")),
+            Err(RuntimeError::NotCallable(Value::Number(_)))
+        ));
+        assert!(matches!(
+            interpreter.eval(&parse("undefined()")),
+            Err(RuntimeError::UndefinedVariable(_))
+        ));
+    }
+
+    #[test]
+    fn recursive_function() {
+        let values = interpret(
+            "fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            fib(10);",
+        )
+        .unwrap();
+        assert_eq!(values, vec![Value::Number(55.0)]);
+    }
+
+    #[test]
+    fn return_unwinds_nested_statements() {
+        let values = interpret(
+            "var x = \"global\";
+            fun find(limit) {
+                var x = \"local\";
+                if (limit > 0) {
+                    var i = 0;
+                    while (true) {
+                        {
+                            if (i == limit) return i;
+                        }
+                        i = i + 1;
+                    }
+                } else {
+                    for (var j = 0; ; j = j + 1) {
+                        return;
+                    }
+                }
+                return \"unreachable\";
+            }
+            find(3);
+            find(0);
+            x;",
+        )
+        .unwrap();
+        // The scopes the return left are no longer current.
+        assert_eq!(
+            values,
+            vec![
+                Value::Number(3.0),
+                Value::Nil,
+                Value::String(String::from("global"))
+            ]
+        );
+    }
+
+    // Deep Lox calls need more native stack than a test thread has.
+    fn on_large_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn unbounded_recursion() {
+        on_large_stack(|| {
+            let mut interpreter = Interpreter::default();
+            let error = interpreter
+                .interpret("fun forever(n) { return forever(n + 1); } forever(0);")
+                .unwrap_err();
+            assert!(matches!(
+                error.value,
+                Error::Runtime(RuntimeError::StackOverflow(DEFAULT_MAX_CALL_DEPTH))
+            ));
+            // Unwinding the overflow resets the depth.
+            assert_eq!(
+                interpreter
+                    .interpret("fun down(n) { if (n > 0) return down(n - 1); return n; } down(90);")
+                    .unwrap(),
+                vec![Value::Number(0.0)]
+            );
+            interpreter.max_call_depth = 10;
+            assert!(matches!(
+                interpreter.interpret("down(10);").unwrap_err().value,
+                Error::Runtime(RuntimeError::StackOverflow(10))
+            ));
+            assert!(interpreter.interpret("down(9);").is_ok());
+        });
+    }
+
+    #[test]
+    fn deep_recursion() {
+        on_large_stack(|| {
+            let mut interpreter = Interpreter::default();
+            assert_eq!(
+                interpreter
+                    .interpret("fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); } count(1000);")
+                    .unwrap(),
+                vec![Value::Number(1000.0)]
+            );
+        });
+    }
+
+    #[test]
+    fn logical_operators() {
+        let values = interpret(
+            "nil or \"default\"; 0 or 1; false and undefined; true or undefined; 1 and 2; nil and 1;",
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::String(String::from("default")),
+                Value::Number(0.0),
+                Value::Boolean(false),
+                Value::Boolean(true),
+                Value::Number(2.0),
+                Value::Nil,
+            ]
+        );
+        assert!(matches!(
+            interpret("true and undefined;"),
+            Err(Spanned {
+                value: Error::Runtime(RuntimeError::UndefinedVariable(_)),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn counter_closure() {
+        let values = interpret(
+            "fun makeCounter() {
+                var count = 0;
+                fun counter() {
+                    count = count + 1;
+                    return count;
+                }
+                return counter;
+            }
+            var first = makeCounter();
+            var second = makeCounter();
+            first();
+            first();
+            second();",
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(1.0)]
+        );
+    }
+
+    #[test]
+    fn call_user_function_with_wrong_arity() {
+        assert!(matches!(
+            interpret("fun f(a, b) { return a; } f(1);"),
+            Err(Spanned {
+                value: Error::Runtime(RuntimeError::Arity {
+                    expected: 2,
+                    got: 1
+                }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn break_exits_loop() {
+        let values = interpret(
+            "var i = 0;
+            while (true) {
+                i = i + 1;
+                if (i == 3) break;
+            }
+            i;",
+        )
+        .unwrap();
+        assert_eq!(values, vec![Value::Number(3.0)]);
+    }
+
+    #[test]
+    fn continue_skips_iteration() {
+        let values = interpret(
+            "var sum = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+                if (i == 2) continue;
+                sum = sum + i;
+            }
+            sum;",
+        )
+        .unwrap();
+        assert_eq!(values, vec![Value::Number(8.0)]);
+    }
+
+    #[test]
+    fn break_from_nested_loop() {
+        let values = interpret(
+            "var count = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = 0;
+                while (j < 10) {
+                    j = j + 1;
+                    if (j > 2) break;
+                    count = count + 1;
+                }
+            }
+            count;",
+        )
+        .unwrap();
+        assert_eq!(values, vec![Value::Number(6.0)]);
+    }
+
+    #[test]
+    fn error_messages() {
+        let messages = [
+            (
+                RuntimeError::TypeError(TypeError {
+                    expected: &[Type::Number],
+                    actual: Value::String(String::from("a")),
+                }),
+                "expected Number but got String",
+            ),
+            (
+                RuntimeError::Operand(
+                    BinaryOperator::Sub,
+                    Operand::Right,
+                    TypeError {
+                        expected: &[Type::Number],
+                        actual: Value::Nil,
+                    },
+                ),
+                "right operand of '-': expected Number but got Nil",
+            ),
+            (
+                RuntimeError::MismatchedTypes(Type::Number, Type::Boolean),
+                "can't compare Number with Boolean",
+            ),
+            (
+                RuntimeError::UndefinedVariable(Symbol::intern("x")),
+                "undefined variable 'x'",
+            ),
+            (
+                RuntimeError::NotCallable(Value::Nil),
+                "can only call functions, got Nil",
+            ),
+            (
+                RuntimeError::Arity {
+                    expected: 2,
+                    got: 3,
+                },
+                "expected 2 arguments but got 3",
+            ),
+        ];
+        for (error, message) in messages {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn operand_errors_name_the_operator() {
+        for (source, message) in [
+            (
+                "\"a\" - 1",
+                "left operand of '-': expected Number but got String",
+            ),
+            (
+                "1 / nil",
+                "right operand of '/': expected Number but got Nil",
+            ),
+            (
+                "2 ** true",
+                "right operand of '**': expected Number but got Boolean",
+            ),
+            (
+                "nil * 2",
+                "left operand of '*': expected Number or String but got Nil",
+            ),
+            (
+                "1 < \"b\"",
+                "right operand of '<': expected Number but got String",
+            ),
+            ("\"ab\" * -2", "can't repeat a string -2 times"),
+            ("\"ab\" * 2.5", "can't repeat a string 2.5 times"),
+            (
+                "\"ab\" * 100000000000000000000",
+                "can't repeat a string 100000000000000000000 times",
+            ),
+            (
+                "1 << 0.5",
+                "right operand of '<<': expected an integer but got 0.5",
+            ),
+        ] {
+            assert_eq!(
+                eval(parse(source)).unwrap_err().to_string(),
+                message,
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn this_and_super_without_class() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&Expression::This),
+            Err(RuntimeError::OutsideClass("this"))
+        ));
+        assert_eq!(
+            interpreter
+                .eval(&Expression::Super(Symbol::intern("init")))
+                .unwrap_err()
+                .to_string(),
+            "can't use 'super' outside of a class"
+        );
+    }
+
+    #[test]
+    fn comparison_operand_errors() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&parse("true > 1")),
+            Err(RuntimeError::Operand(
+                BinaryOperator::Greater,
+                Operand::Left,
+                TypeError {
+                    actual: Value::Boolean(true),
+                    ..
+                }
+            ))
+        ));
+        assert!(matches!(
+            interpreter.eval(&parse("1 <= \"a\"")),
+            Err(RuntimeError::Operand(
+                BinaryOperator::LessEqual,
+                Operand::Right,
+                TypeError {
+                    actual: Value::String(_),
+                    ..
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn deep_expression() {
+        let mut expression = Expression::Literal(Value::Boolean(true));
+        for _ in 0..100_000 {
+            let operand = Spanned {
+                value: expression,
+                span: Span { start: 0, end: 0 },
+            };
+            expression = Expression::Unary(UnaryOperator::Neg, Box::new(operand));
+        }
+        let result = Interpreter::default().eval(&expression);
+        assert!(matches!(result, Err(RuntimeError::TypeError(_))));
+        // `eval` drops the tree once it's done with it.
+        assert!(matches!(eval(expression), Err(RuntimeError::TypeError(_))));
+    }
+
+    #[test]
+    fn comma_yields_last_value() {
+        let values = interpret("var a = 0; var b = 0; a = 1, b = a + 1, a + b; a; b;").unwrap();
+        assert_eq!(
+            values,
+            vec![Value::Number(3.0), Value::Number(1.0), Value::Number(2.0)]
+        );
+    }
+
+    #[test]
+    fn comma_inside_call_arguments() {
+        let values = interpret("fun f(a, b) { return a - b; } f(5, 3); f((1, 5), 3);").unwrap();
+        assert_eq!(values, vec![Value::Number(2.0), Value::Number(2.0)]);
+    }
+
+    #[test]
+    fn unary_plus() {
+        assert_eq!(eval(parse("+5")).unwrap(), Value::Number(5.0));
+        assert_eq!(eval(parse("+(2 + 3)")).unwrap(), Value::Number(5.0));
+        assert!(matches!(
+            eval(parse("+\"x\"")),
+            Err(RuntimeError::TypeError(TypeError {
+                actual: Value::String(_),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        assert_eq!(eval(parse("6 & 3")).unwrap(), Value::Number(2.0));
+        assert_eq!(eval(parse("6 | 3")).unwrap(), Value::Number(7.0));
+        assert_eq!(eval(parse("6 ^ 3")).unwrap(), Value::Number(5.0));
+        assert_eq!(eval(parse("1 << 4")).unwrap(), Value::Number(16.0));
+        assert_eq!(eval(parse("-16 >> 2")).unwrap(), Value::Number(-4.0));
+        assert_eq!(eval(parse("1 | 2 == 3")).unwrap(), Value::Boolean(true));
+    }
+
+    #[test]
+    fn bitwise_requires_integers() {
+        let error = eval(parse("1.5 & 1")).unwrap_err();
+        assert!(matches!(
+            error,
+            RuntimeError::NotAnInteger(BinaryOperator::BitAnd, Operand::Left, n) if n == 1.5
+        ));
+        assert_eq!(
+            error.to_string(),
+            "left operand of '&': expected an integer but got 1.5"
+        );
+        assert!(matches!(
+            eval(parse("1 | true")),
+            Err(RuntimeError::Operand(
+                BinaryOperator::BitOr,
+                Operand::Right,
+                _
+            ))
+        )); // Integral, but outside the range of i64.
+        assert!(matches!(
+            eval(parse("100000000000000000000 & 1")),
+            Err(RuntimeError::NotAnInteger(BinaryOperator::BitAnd, Operand::Left, n)) if n == 1e20
+        ));
+        assert!(matches!(
+            eval(parse("1 | 9223372036854775808")),
+            Err(RuntimeError::NotAnInteger(
+                BinaryOperator::BitOr,
+                Operand::Right,
+                _
+            ))
+        ));
+        assert_eq!(
+            eval(parse("-9223372036854775808 | 0")).unwrap(),
+            Value::Number(i64::MIN as Number)
+        );
+    }
+
+    #[test]
+    fn list_literal_and_index() {
+        let values =
+            interpret("var list = [1, \"two\", 1 + 2]; list; list[1]; list[2]; [];").unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::List(vec![
+                    Value::Number(1.0),
+                    Value::String(String::from("two")),
+                    Value::Number(3.0),
+                ]),
+                Value::String(String::from("two")),
+                Value::Number(3.0),
+                Value::List(vec![]),
+            ]
+        );
+        assert_eq!(format!("{:?}", values[0]), "[1, two, 3]");
+    }
+
+    #[test]
+    fn negative_index_counts_from_end() {
+        assert_eq!(eval(parse("[1, 2, 3][-1]")).unwrap(), Value::Number(3.0));
+        assert_eq!(eval(parse("[1, 2, 3][-3]")).unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn index_out_of_bounds() {
+        for (source, index) in [("[1, 2, 3][3]", 3.0), ("[1, 2, 3][-4]", -4.0)] {
+            let error = eval(parse(source)).unwrap_err();
+            assert!(matches!(
+                error,
+                RuntimeError::IndexOutOfBounds { index: i, len: 3 } if i == index
+            ));
+        }
+        assert_eq!(
+            eval(parse("[][0]")).unwrap_err().to_string(),
+            "index 0 out of bounds for length 0"
+        );
+        assert!(matches!(
+            eval(parse("[1][true]")),
+            Err(RuntimeError::TypeError(_))
+        ));
+        assert!(matches!(
+            eval(parse("1[0]")),
+            Err(RuntimeError::TypeError(TypeError {
+                expected: [Type::List, Type::String, Type::Map],
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn fractional_index() {
+        for source in ["[1, 2, 3][1.5]", "\"abc\"[-0.5]", "[1][0 / 0]"] {
+            assert!(
+                matches!(eval(parse(source)), Err(RuntimeError::InvalidIndex(_))),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            eval(parse("[1][2.5]")).unwrap_err().to_string(),
+            "expected an integer index but got 2.5"
+        );
+    }
+
+    #[test]
+    fn string_index_and_len() {
+        let values =
+            interpret("\"hello\"[0]; \"hello\"[-1]; len(\"hello\"); len([1, 2]); len(\"\");")
+                .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::String(String::from("h")),
+                Value::String(String::from("o")),
+                Value::Number(5.0),
+                Value::Number(2.0),
+                Value::Number(0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_byte_string_index() {
+        let values = interpret("var s = \"日本語\"; s[1]; s[-1]; len(s);").unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::String(String::from("本")),
+                Value::String(String::from("語")),
+                Value::Number(3.0),
+            ]
+        );
+        assert!(matches!(
+            eval(parse("\"héllo\"[5]")),
+            Err(RuntimeError::IndexOutOfBounds { len: 5, .. })
+        ));
+        assert!(matches!(
+            interpret("len(1);"),
+            Err(Spanned {
+                value: Error::Runtime(RuntimeError::TypeError(_)),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn string_coercion() {
+        let mut interpreter = Interpreter::default();
+        assert!(matches!(
+            interpreter.eval(&parse("\"x\" + 1")),
+            Err(RuntimeError::Operand(
+                BinaryOperator::Add,
+                Operand::Right,
+                _
+            ))
+        ));
+        let mut interpreter = Interpreter {
+            coerce_strings: true,
+            ..Default::default()
+        };
+        for (source, expected) in [
+            ("\"x\" + 1", "x1"),
+            ("1.5 + \"x\"", "1.5x"),
+            ("\"x\" + nil", "xnil"),
+            ("\"a\" + \"b\"", "ab"),
+        ] {
+            assert_eq!(
+                interpreter.eval(&parse(source)).unwrap(),
+                Value::String(String::from(expected))
+            );
+        }
+        assert_eq!(
+            interpreter.eval(&parse("1 + 2")).unwrap(),
+            Value::Number(3.0)
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_to_output() {
+        let buffer = Buffer::default();
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        let source = "print 1; print \"two\"; print [nil, true];";
+        for statement in Parser::new(source, Scanner::new(source)) {
+            interpreter.execute(statement.value.unwrap()).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(buffer.0.take()).unwrap(),
+            "1\ntwo\n[nil, true]\n"
+        );
+    }
+
+    #[test]
+    fn print_precision() {
+        let buffer = Buffer::default();
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        interpreter.interpret("print 1.0 / 3.0;").unwrap();
+        interpreter.precision = Some(4);
+        interpreter
+            .interpret("print 1.0 / 3.0; print [2, 123456];")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.0.take()).unwrap(),
+            "0.3333333333333333\n0.3333\n[2, 1.235e5]\n"
+        );
+    }
+
+    #[test]
+    fn run_program_past_errors() {
+        let source = "print 1; 1 + nil; var 1; y; print 2; 3";
+        let statements = || Parser::new(source, Scanner::new(source));
+        let buffer = Buffer::default();
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        interpreter.continue_on_error = true;
+        let results = interpreter.run_program(statements()).collect::<Vec<_>>();
+        assert!(matches!(
+            results[..],
+            [
+                Err(Spanned {
+                    value: Error::Runtime(RuntimeError::Operand(_, Operand::Right, _)),
+                    ..
+                }),
+                Err(Spanned {
+                    value: Error::Parse(_),
+                    ..
+                }),
+                Err(Spanned {
+                    value: Error::Runtime(RuntimeError::UndefinedVariable(_)),
+                    ..
+                }),
+                Ok(Value::Number(n)),
+            ] if n == 3.0
+        ));
+        assert_eq!(String::from_utf8(buffer.0.take()).unwrap(), "1\n2\n");
+
+        // Without the flag the stream ends at the first error.
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        assert_eq!(interpreter.run_program(statements()).count(), 1);
+        assert_eq!(String::from_utf8(buffer.0.take()).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn number_natives() {
+        let values = interpret("min(3, -1); max(3, -1); abs(-2.5); abs(4);").unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::Number(-1.0),
+                Value::Number(3.0),
+                Value::Number(2.5),
+                Value::Number(4.0),
+            ]
+        );
+        for source in ["min(1, \"2\");", "max(\"1\", 2);", "abs(\"-1\");"] {
+            assert!(
+                matches!(
+                    interpret(source).unwrap_err().value,
+                    Error::Runtime(RuntimeError::TypeError(_))
+                ),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(
+            interpret("min(1);").unwrap_err().value,
+            Error::Runtime(RuntimeError::Arity {
+                expected: 2,
+                got: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn maps() {
+        let values = interpret(
+            "var m = {\"a\": 1, 2: true};
+            m[\"a\"]; m[2]; m[\"missing\"];
+            m[\"a\"] = 10; m[\"a\"];
+            var alias = m; alias[-0] = \"zero\"; m[0];
+            len(m);",
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::Number(1.0),
+                Value::Boolean(true),
+                Value::Nil,
+                Value::Number(10.0),
+                Value::Number(10.0),
+                Value::String(String::from("zero")),
+                Value::String(String::from("zero")),
+                Value::Number(3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn self_referential_maps() {
+        let mut interpreter = Interpreter::default();
+        interpreter
+            .interpret("var m = {}; m[0] = m; var a = {}; var b = {}; a[0] = b; b[0] = a;")
+            .unwrap();
+        // Maps are equal only to themselves, however they're nested.
+        assert_eq!(
+            interpreter
+                .interpret("m == m; a == b; a == a; ({}) == {};")
+                .unwrap(),
+            [true, false, true, false].map(Value::Boolean)
+        );
+        let print = |interpreter: &mut Interpreter, source| {
+            interpreter
+                .interpret(source)
+                .unwrap()
+                .pop()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(print(&mut interpreter, "m;"), "{0: {...}}");
+        assert_eq!(print(&mut interpreter, "a;"), "{0: {0: {...}}}");
+        assert_eq!(
+            print(&mut interpreter, "[m, m];"),
+            "[{0: {...}}, {0: {...}}]"
+        );
+    }
+
+    #[test]
+    fn invalid_map_keys() {
+        for source in [
+            "print {0 / 0: 1};",
+            "print {}[{}];",
+            "var m = {}; m[[0 / 0]] = 1;",
+        ] {
+            assert!(
+                matches!(
+                    interpret(source).unwrap_err().value,
+                    Error::Runtime(RuntimeError::InvalidKey(_))
+                ),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(
+            interpret("var l = [1]; l[0] = 2;").unwrap_err().value,
+            Error::Runtime(RuntimeError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn expression_statements_print_nothing() {
+        let buffer = Buffer::default();
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        let source = "clock(); 1 + 2;";
+        for statement in Parser::new(source, Scanner::new(source)) {
+            interpreter.execute(statement.value.unwrap()).unwrap();
+        }
+        assert!(buffer.0.take().is_empty());
+    }
+
+    #[test]
+    fn get_without_properties() {
+        let error = eval(parse("\"text\".length")).unwrap_err();
+        assert!(matches!(
+            &error,
+            RuntimeError::NoProperties(Value::String(_))
+        ));
+        assert_eq!(error.to_string(), "String values have no properties");
+        assert!(matches!(
+            eval(parse("missing.field")),
+            Err(RuntimeError::UndefinedVariable(_))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+pub mod analysis;
+pub mod ast;
+pub mod compiler;
+pub mod environment;
+pub mod intern;
+pub mod interpreter;
+pub mod native;
+pub mod optimize;
+pub mod parser;
+pub mod precedence;
+pub mod scanner;
+pub mod span;
+pub mod value;
+pub mod vm;
+
+use std::fmt::{self, Display};
+
+use ast::Statement;
+
+pub use interpreter::{eval, eval_as, Interpreter, RuntimeError};
+pub use parser::{parse_expression, Parser};
+pub use scanner::{ScanError, Scanner, Token};
+pub use span::{Span, Spanned};
+pub use value::Value;
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(parser::Error),
+    Runtime(RuntimeError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn parse(source: &str) -> (Vec<Spanned<Statement>>, Vec<Spanned<parser::Error>>) {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    for result in Parser::new(source, Scanner::new(source)) {
+        match result.value {
+            Ok(statement) => statements.push(Spanned {
+                value: statement,
+                span: result.span,
+            }),
+            Err(error) => errors.push(Spanned {
+                span: error.span().unwrap_or(result.span),
+                value: error,
+            }),
+        }
+    }
+    (statements, errors)
+}
+
+// Whether `source` could be extended into valid input, i.e. parsing only
+// failed because it ran out of tokens.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut parser = Parser::new(source, Scanner::new(source));
+    parser.by_ref().for_each(drop);
+    parser.is_incomplete()
+}
+
+pub fn interpret(source: &str) -> Result<Vec<Value>, Spanned<Error>> {
+    Interpreter::default().interpret(source)
+}
+
+pub fn interpret_all(source: &str) -> (Vec<Value>, Vec<Spanned<Error>>) {
+    Interpreter::default().interpret_all(source)
+}
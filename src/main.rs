@@ -1,116 +1,101 @@
-pub mod ast;
-pub mod interpreter;
-pub mod parser;
-pub mod scanner;
-pub mod span;
-pub mod value;
-pub mod vm;
+use std::{
+    cell::RefCell,
+    io::{stdin, BufRead, BufReader},
+    rc::Rc,
+};
 
-use std::io::{stdin, BufRead, BufReader};
-
-use interpreter::eval;
-use parser::Parser;
-use scanner::{Scanner, Token};
-use span::Span;
+use loxer::{
+    environment::Environment,
+    native,
+    span::{underline, LineMap},
+    Interpreter,
+};
 
 pub type DynResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+// Every Lox call recurses in Rust, so the default call depth needs more
+// stack than the main thread has.
+const STACK_SIZE: usize = 64 << 20;
+
 fn main() -> DynResult<()> {
     let args = std::env::args().collect::<Vec<String>>();
-    match args.len() {
-        1 => run_prompt(),
-        2 => run_file(&args[1]),
-        _ => Err("Usage loxer [script]".into()),
-    }
+    let lox = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            match args.len() {
+                1 => run_prompt(),
+                2 => run_file(&args[1]),
+                _ => Err("Usage loxer [script]".into()),
+            }
+            .map_err(|error| error.to_string())
+        })?;
+    Ok(lox.join().map_err(|_| "interpreter panicked")??)
 }
 
 fn run_prompt() -> DynResult<()> {
     let mut stdin = BufReader::new(stdin().lock());
     let mut source = String::new();
+    let mut line = String::new();
+    let environment = globals();
     loop {
-        println!(">");
+        println!("{}", if source.is_empty() { ">" } else { "..." });
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        // A blank line abandons a partial entry.
+        if line.trim().is_empty() && !source.is_empty() {
+            source.clear();
+            continue;
+        }
+        source.push_str(&line);
+        if loxer::is_incomplete(&source) {
+            continue;
+        }
+        run(&environment, &source, true)?;
         source.clear();
-        stdin.read_line(&mut source)?;
-        run(&source)?;
     }
 }
 
 fn run_file(path: &str) -> DynResult<()> {
     let source = std::fs::read_to_string(path)?;
-    run(&source)
+    run(&globals(), &source, false)
 }
 
-fn run(input: &str) -> DynResult<()> {
-    let lines = count_lines(input);
-    for result in Parser::new(
-        input,
-        Scanner::new(input).filter(|token| token.value != Token::Comment),
-    ) {
-        match result.value {
-            Ok(expression) => match eval(expression) {
-                Ok(value) => println!("{:?}", value),
-                Err(error) => {
-                    println_span(input, &lines, result.span);
-                    println!("{:?}", error);
-                }
-            },
-            Err(error) => {
-                println_span(input, &lines, result.span);
-                println!("{:?}", error);
-            }
-        }
-        // let lexeme = &input[token.start as usize..token.end as usize];
-        // match token.tt {
-        //     TokenType::Unknown => {
-        //         eprintln!("Unknown token {} at line {}.", lexeme, token.line);
-        //     }
-        //     TokenType::String if !lexeme.ends_with('"') => {
-        //         eprintln!(
-        //             "Unterminated string {} at line {}.",
-        //             lexeme.replace('\n', "\\n"),
-        //             token.line
-        //         );
-        //     }
-        //     _ => {
-        //         println!("'{}' :: {:?}", lexeme, token.tt,);
-        //     }
-        // }
-    }
-    Ok(())
+fn globals() -> Rc<RefCell<Environment>> {
+    let mut globals = Environment::default();
+    native::define_globals(&mut globals);
+    Rc::new(RefCell::new(globals))
 }
 
-fn println_span(input: &str, lines: &[i32], span: Span) {
-    let low = lines.binary_search(&span.start).unwrap_or_else(|x| x) - 1;
-    let high = lines.binary_search(&(span.end - 1)).unwrap_or_else(|x| x) - 1;
-    let line_start = (lines[low] + 1) as usize;
-    let line_end = (lines[high + 1]) as usize;
-    println!(
-        "lines {:?}, span {:?}, low: {}, high: {}, range: {:?}",
-        lines,
-        span,
-        low,
-        high,
-        line_start..line_end
-    );
-    println!("{}", &input[line_start..line_end]);
-    for _ in line_start..span.start as usize {
-        print!(" ");
-    }
-    for _ in span.start..span.end {
-        print!("^");
-    }
-    for _ in span.end as usize..line_end {
-        print!(" ");
+// With `echo` set, the values of expression statements are printed, as the
+// prompt does; other statements run silently.
+fn run(environment: &Rc<RefCell<Environment>>, input: &str, echo: bool) -> DynResult<()> {
+    let lines = LineMap::new(input);
+    let (statements, errors) = loxer::parse(input);
+    if !errors.is_empty() {
+        for error in errors {
+            print!("{}", underline(input, &lines, error.span));
+            println!("{}", error.value);
+        }
+        return Ok(());
     }
-    println!();
-}
-
-pub fn count_lines(input: &str) -> Vec<i32> {
-    let mut lines = vec![-1];
-    for (index, ch) in input.char_indices() {
-        if ch == '\n' {
-            lines.push(index as i32);
+    // At the prompt, a line that fails is undone as a whole.
+    let snapshot = echo.then(|| environment.borrow().snapshot());
+    let mut interpreter = Interpreter::with_environment(environment.clone());
+    for statement in statements {
+        match interpreter.execute(statement.value) {
+            Ok(Some(value)) if echo => println!("{}", value),
+            Ok(_) => {}
+            Err(error) => {
+                print!("{}", underline(input, &lines, statement.span));
+                println!("{}", error);
+                if let Some(snapshot) = snapshot {
+                    environment.borrow_mut().restore(snapshot);
+                    return Ok(());
+                }
+            }
         }
     }
-    lines
+    Ok(())
 }
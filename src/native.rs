@@ -0,0 +1,70 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    environment::Environment,
+    intern::Symbol,
+    interpreter::RuntimeError,
+    value::{NativeFunction, Number, Type, TypeError, Value, Variant},
+};
+
+pub fn define_globals(globals: &mut Environment) {
+    define(globals, "clock", 0, clock);
+    define(globals, "len", 1, len);
+    define(globals, "min", 2, min);
+    define(globals, "max", 2, max);
+    define(globals, "abs", 1, abs);
+}
+
+fn define(
+    globals: &mut Environment,
+    name: &'static str,
+    arity: usize,
+    function: fn(&[Value]) -> Result<Value, RuntimeError>,
+) {
+    globals.define(
+        Symbol::intern(name),
+        Value::NativeFn(NativeFunction {
+            name,
+            arity,
+            function,
+        }),
+    );
+}
+
+fn clock(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+fn len(args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match &args[0] {
+        Value::String(string) => string.chars().count(),
+        Value::List(values) => values.len(),
+        Value::Map(entries) => entries.borrow().len(),
+        value => {
+            return Err(RuntimeError::TypeError(TypeError {
+                expected: &[Type::String, Type::List, Type::Map],
+                actual: value.clone(),
+            }))
+        }
+    };
+    Ok(Value::Number(len as f64))
+}
+
+fn min(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number(&args[0])?.min(number(&args[1])?)))
+}
+
+fn max(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number(&args[0])?.max(number(&args[1])?)))
+}
+
+fn abs(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number(&args[0])?.abs()))
+}
+
+fn number(value: &Value) -> Result<Number, RuntimeError> {
+    Number::from_value(value.clone()).map_err(RuntimeError::TypeError)
+}
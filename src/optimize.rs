@@ -0,0 +1,147 @@
+use crate::{
+    ast::{BinaryOperator, Expression},
+    interpreter::Interpreter,
+    value::Value,
+};
+
+// Replaces every subexpression built only from literals with its value.
+// Anything that would fail at runtime, including division by zero and
+// comparisons that strict equality rejects, is left for the runtime to report.
+pub fn fold_constants(mut expression: Expression) -> Expression {
+    let mut interpreter = Interpreter::default();
+    interpreter.strict_equality = true;
+    fold(&mut interpreter, &mut expression);
+    expression
+}
+
+// Folding happens in place, so a folded node keeps the span of the source it
+// replaces.
+fn fold(interpreter: &mut Interpreter, expression: &mut Expression) {
+    match expression {
+        Expression::Unary(_, operand)
+        | Expression::Assign(_, operand)
+        | Expression::Get(operand, _) => fold(interpreter, &mut operand.value),
+        Expression::Binary(_, left, right)
+        | Expression::Logical(_, left, right)
+        | Expression::Index(left, right) => {
+            fold(interpreter, &mut left.value);
+            fold(interpreter, &mut right.value);
+        }
+        Expression::SetIndex(object, index, value) => {
+            fold(interpreter, &mut object.value);
+            fold(interpreter, &mut index.value);
+            fold(interpreter, &mut value.value);
+        }
+        Expression::Call(callee, arguments) => {
+            fold(interpreter, &mut callee.value);
+            for argument in arguments {
+                fold(interpreter, &mut argument.value);
+            }
+        }
+        Expression::Comma(nodes) | Expression::List(nodes) => {
+            for node in nodes {
+                fold(interpreter, &mut node.value);
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                fold(interpreter, &mut key.value);
+                fold(interpreter, &mut value.value);
+            }
+        }
+        Expression::Literal(_)
+        | Expression::Variable(_)
+        | Expression::This
+        | Expression::Super(_) => {}
+    }
+    if is_constant(expression) {
+        if let Ok(value) = interpreter.eval(expression) {
+            *expression = Expression::Literal(value);
+        }
+    }
+}
+
+fn is_constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::Unary(_, operand) => matches!(operand.value, Expression::Literal(_)),
+        Expression::Binary(operator, left, right) => match (&left.value, &right.value) {
+            (_, Expression::Literal(Value::Number(divisor)))
+                if *operator == BinaryOperator::Div && *divisor == 0.0 =>
+            {
+                false
+            }
+            (Expression::Literal(_), Expression::Literal(_)) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::{print_ast, Statement},
+        parser::Parser,
+        scanner::Scanner,
+    };
+
+    fn folded(source: &str) -> Expression {
+        match Parser::new(source, Scanner::new(source)).next() {
+            Some(statement) => match statement.value {
+                Ok(Statement::Expression(expression)) => fold_constants(expression),
+                statement => panic!("expected an expression, got {:?}", statement),
+            },
+            None => panic!("no statement in {:?}", source),
+        }
+    }
+
+    fn number(value: f64) -> Expression {
+        Expression::Literal(Value::Number(value))
+    }
+
+    #[test]
+    fn fold_arithmetic() {
+        assert_eq!(folded("2 + 3 * 4"), number(14.0));
+        assert_eq!(folded("-(2 - 5) / 2"), number(1.5));
+        assert_eq!(
+            folded("\"a\" + \"b\" == \"ab\""),
+            Expression::Literal(Value::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn keep_variables() {
+        assert_eq!(print_ast(&folded("x + 2 * 3")), "(+ x 6)");
+        assert_eq!(
+            print_ast(&folded("f(1 + 1, [2 * y])")),
+            "(call f 2 (list (* 2 y)))"
+        );
+    }
+
+    #[test]
+    fn leave_errors_for_runtime() {
+        for source in [
+            "1 / 0",
+            "1 / (2 - 2)",
+            "1 + true",
+            "1 == \"1\"",
+            "-nil",
+            "\"ab\" * 100000000000000000000",
+            "\"ab\" * -2.5",
+        ] {
+            assert!(
+                !matches!(folded(source), Expression::Literal(_)),
+                "{} was folded",
+                source
+            );
+        }
+        let expression = folded("(1 + 1) / 0");
+        assert_eq!(print_ast(&expression), "(/ 2 0)");
+        // The folded operand still points at the source it came from.
+        let Expression::Binary(_, left, _) = &expression else {
+            panic!("expected a division");
+        };
+        assert_eq!((left.span.start, left.span.end), (0, 7));
+    }
+}
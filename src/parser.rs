@@ -1,142 +1,718 @@
-use std::iter::Peekable;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    rc::Rc,
+};
 
 use crate::{
-    ast::{BinaryOperator, Expression, UnaryOperator},
-    scanner::Token,
+    ast::{BinaryOperator, Expression, Function, LogicalOperator, Node, Statement, UnaryOperator},
+    intern::Symbol,
+    precedence::{Associativity, Precedence},
+    scanner::{ScanError, Scanner, Token},
     span::{Span, Spanned},
     value::Value,
 };
 
+const MAX_ARGUMENTS: usize = 255;
+const DEFAULT_MAX_DEPTH: usize = 128;
+
 #[derive(Debug)]
 pub enum Error {
     ExpectedPrimary,
     Expected(Token),
+    ExpectedOneOf(Vec<Token>),
+    UnclosedCall(Span),
+    UnexpectedCharacter(String, Span),
+    UnterminatedString(Span),
+    UnterminatedChar(Span),
+    TooManyArguments,
+    TooManyParameters,
+    InvalidAssignmentTarget,
+    ReturnOutsideFunction,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
     MalformedNumber,
     MalformedString,
+    MalformedChar,
+    NestingTooDeep,
+    UnexpectedEof,
+    ChainedComparison,
+    TrailingInput(Span),
+    DuplicateLocal(Symbol),
+    ThisOutsideClass,
+    SuperOutsideClass,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ExpectedPrimary => write!(f, "expected expression"),
+            Error::Expected(token) => write!(f, "expected {}", token),
+            Error::ExpectedOneOf(tokens) => {
+                write!(f, "expected ")?;
+                for (index, token) in tokens.iter().enumerate() {
+                    if index + 1 == tokens.len() && index > 0 {
+                        write!(f, " or ")?;
+                    } else if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", token)?;
+                }
+                Ok(())
+            }
+            Error::UnclosedCall(_) => write!(f, "unclosed '(' in call"),
+            Error::UnterminatedString(_) => write!(f, "unterminated string"),
+            Error::UnterminatedChar(_) => write!(f, "unterminated character literal"),
+            Error::UnexpectedCharacter(lexeme, _) => {
+                write!(f, "unexpected character '{}'", lexeme)
+            }
+            Error::TooManyArguments => {
+                write!(f, "can't have more than {} arguments", MAX_ARGUMENTS)
+            }
+            Error::TooManyParameters => {
+                write!(f, "can't have more than {} parameters", MAX_ARGUMENTS)
+            }
+            Error::InvalidAssignmentTarget => write!(f, "invalid assignment target"),
+            Error::ReturnOutsideFunction => write!(f, "can't return from top-level code"),
+            Error::BreakOutsideLoop => write!(f, "can't use 'break' outside of a loop"),
+            Error::ContinueOutsideLoop => write!(f, "can't use 'continue' outside of a loop"),
+            Error::ThisOutsideClass => write!(f, "can't use 'this' outside of a class"),
+            Error::SuperOutsideClass => write!(f, "can't use 'super' outside of a class"),
+            Error::MalformedNumber => write!(f, "malformed number literal"),
+            Error::MalformedString => write!(f, "malformed string literal"),
+            Error::MalformedChar => write!(
+                f,
+                "malformed character literal; it must hold exactly one character"
+            ),
+            Error::NestingTooDeep => write!(f, "expression is nested too deeply"),
+            Error::UnexpectedEof => write!(f, "unexpected end of input"),
+            Error::TrailingInput(_) => write!(f, "expected end of input"),
+            Error::DuplicateLocal(name) => {
+                write!(f, "'{}' is already declared in this scope", name)
+            }
+            Error::ChainedComparison => write!(
+                f,
+                "comparisons can't be chained; join them instead, as in 'a < b and b < c'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    // The offending token, when it's narrower than the whole statement.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::UnclosedCall(span)
+            | Error::UnexpectedCharacter(_, span)
+            | Error::UnterminatedString(span)
+            | Error::UnterminatedChar(span)
+            | Error::TrailingInput(span) => Some(*span),
+            _ => None,
+        }
+    }
 }
 
 pub struct Parser<'a, I>
 where
-    I: Iterator<Item = Spanned<Token>>,
+    I: Iterator<Item = Spanned<Result<Token, ScanError>>>,
 {
     input: &'a str,
-    tokens: Peekable<I>,
+    tokens: I,
+    lookahead: VecDeque<Spanned<Token>>,
     end: i32,
+    previous: Option<Spanned<Token>>,
+    // The first lexical error of the current statement. No tokens are read
+    // past it until it has been reported.
+    scan_error: Option<Spanned<ScanError>>,
+    function_depth: usize,
+    loop_depth: usize,
+    // Classes aren't parsed yet, so this stays at zero for now.
+    class_depth: usize,
+    // Names declared in each enclosing local scope, innermost last. Globals
+    // may be redeclared, so they aren't tracked.
+    scopes: Vec<Vec<Symbol>>,
+    // A redeclared local doesn't derail parsing, so it's reported once the
+    // statement it's in is complete.
+    duplicate_local: Option<Symbol>,
+    // Set once a statement fails only because the input ran out.
+    incomplete: bool,
+    depth: usize,
+    max_depth: usize,
+    trailing_commas: bool,
+    precedence: Rc<Precedence>,
 }
 
 impl<'a, I> Parser<'a, I>
 where
-    I: Iterator<Item = Spanned<Token>>,
+    I: Iterator<Item = Spanned<Result<Token, ScanError>>>,
 {
     pub fn new(input: &'a str, tokens: I) -> Self {
+        Self::with_precedence(input, tokens, Precedence::default())
+    }
+
+    pub fn with_precedence(input: &'a str, tokens: I, precedence: Precedence) -> Self {
         Self {
             input,
-            tokens: tokens.peekable(),
+            tokens,
+            lookahead: VecDeque::new(),
             end: 0,
+            previous: None,
+            scan_error: None,
+            function_depth: 0,
+            loop_depth: 0,
+            class_depth: 0,
+            scopes: Vec::new(),
+            duplicate_local: None,
+            incomplete: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            trailing_commas: false,
+            precedence: Rc::new(precedence),
+        }
+    }
+
+    pub fn with_max_depth(input: &'a str, tokens: I, max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            ..Self::new(input, tokens)
+        }
+    }
+
+    // Accepts a single trailing comma in argument, parameter, list and map
+    // lists, as in `f(a, b,)`.
+    pub fn with_trailing_commas(input: &'a str, tokens: I) -> Self {
+        Self {
+            trailing_commas: true,
+            ..Self::new(input, tokens)
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Statement, Error> {
+        if self.match_token(Token::Var) {
+            return self.var_declaration();
+        }
+        if self.match_token(Token::Fun) {
+            return self.function();
+        }
+        self.statement()
+    }
+
+    fn var_declaration(&mut self) -> Result<Statement, Error> {
+        let name = self.identifier()?;
+        self.declare(name);
+        let initializer = if self.match_token(Token::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.terminator()?;
+        Ok(Statement::Var(name, initializer))
+    }
+
+    fn function(&mut self) -> Result<Statement, Error> {
+        let name = self.identifier()?;
+        self.declare(name);
+        self.expect(Token::LeftParen)?;
+        let params = self.parse_comma_separated(Token::RightParen, Self::identifier)?;
+        if params.len() > MAX_ARGUMENTS {
+            return Err(Error::TooManyParameters);
+        }
+        self.expect_one_of(&[Token::Comma, Token::RightParen])?;
+        self.expect(Token::LeftBrace)?;
+        self.function_depth += 1;
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        // The parameters share a scope with the body's declarations.
+        let body = self.block_with(&params);
+        self.loop_depth = loop_depth;
+        self.function_depth -= 1;
+        Ok(Statement::Function(Rc::new(Function {
+            name,
+            params,
+            body: body?,
+        })))
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        if self.match_token(Token::Semicolon) {
+            return Ok(Statement::Empty);
+        }
+        if self.match_token(Token::Print) {
+            let expression = self.expression()?;
+            self.terminator()?;
+            return Ok(Statement::Print(expression));
+        }
+        if self.match_token(Token::LeftBrace) {
+            return Ok(Statement::Block(self.block()?));
+        }
+        if self.match_token(Token::If) {
+            return self.if_statement();
+        }
+        if self.match_token(Token::Return) {
+            return self.return_statement();
+        }
+        if self.match_token(Token::While) {
+            return self.while_statement();
+        }
+        if self.match_token(Token::For) {
+            return self.for_statement();
+        }
+        if self.match_token(Token::Break) {
+            if self.loop_depth == 0 {
+                return Err(Error::BreakOutsideLoop);
+            }
+            self.terminator()?;
+            return Ok(Statement::Break);
+        }
+        if self.match_token(Token::Continue) {
+            if self.loop_depth == 0 {
+                return Err(Error::ContinueOutsideLoop);
+            }
+            self.terminator()?;
+            return Ok(Statement::Continue);
+        }
+        let expression = self.expression()?;
+        self.terminator()?;
+        Ok(Statement::Expression(expression))
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, Error> {
+        self.block_with(&[])
+    }
+
+    // Parses a block whose scope starts out declaring `names`.
+    fn block_with(&mut self, names: &[Symbol]) -> Result<Vec<Statement>, Error> {
+        self.scopes.push(Vec::new());
+        for &name in names {
+            self.declare(name);
+        }
+        let statements = self.nested(Self::block_statements);
+        self.scopes.pop();
+        statements
+    }
+
+    fn declare(&mut self, name: Symbol) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains(&name) {
+            self.duplicate_local.get_or_insert(name);
+        } else {
+            scope.push(name);
+        }
+    }
+
+    fn block_statements(&mut self) -> Result<Vec<Statement>, Error> {
+        let mut statements = Vec::new();
+        while !self.match_token(Token::RightBrace) {
+            if self.peek().is_none() {
+                return Err(Error::UnexpectedEof);
+            }
+            statements.push(self.declaration()?);
+        }
+        Ok(statements)
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, Error> {
+        self.expect(Token::LeftParen)?;
+        let condition = self.expression()?;
+        self.expect(Token::RightParen)?;
+        let then_branch = self.nested(Self::statement)?;
+        let else_branch = if self.match_token(Token::Else) {
+            Some(Box::new(self.nested(Self::statement)?))
+        } else {
+            None
+        };
+        Ok(Statement::If(condition, Box::new(then_branch), else_branch))
+    }
+
+    fn while_statement(&mut self) -> Result<Statement, Error> {
+        self.expect(Token::LeftParen)?;
+        let condition = self.expression()?;
+        self.expect(Token::RightParen)?;
+        let body = self.loop_body()?;
+        Ok(Statement::While(condition, Box::new(body)))
+    }
+
+    fn for_statement(&mut self) -> Result<Statement, Error> {
+        self.expect(Token::LeftParen)?;
+        let initializer = if self.match_token(Token::Semicolon) {
+            None
+        } else if self.match_token(Token::Var) {
+            // The loop variable gets a scope of its own.
+            self.scopes.push(Vec::new());
+            let declaration = self.var_declaration();
+            self.scopes.pop();
+            Some(Box::new(declaration?))
+        } else {
+            let expression = self.expression()?;
+            self.expect(Token::Semicolon)?;
+            Some(Box::new(Statement::Expression(expression)))
+        };
+        let condition = self.optional_expression(Token::Semicolon)?;
+        self.expect(Token::Semicolon)?;
+        let increment = self.optional_expression(Token::RightParen)?;
+        self.expect(Token::RightParen)?;
+        let body = self.loop_body()?;
+        Ok(Statement::For(
+            initializer,
+            condition,
+            increment,
+            Box::new(body),
+        ))
+    }
+
+    fn optional_expression(&mut self, terminator: Token) -> Result<Option<Expression>, Error> {
+        match self.peek() {
+            Some(token) if token.value == terminator => Ok(None),
+            _ => self.expression().map(Some),
+        }
+    }
+
+    fn loop_body(&mut self) -> Result<Statement, Error> {
+        self.loop_depth += 1;
+        let body = self.nested(Self::statement);
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn return_statement(&mut self) -> Result<Statement, Error> {
+        if self.function_depth == 0 {
+            return Err(Error::ReturnOutsideFunction);
+        }
+        let value = match self.peek() {
+            Some(token) if token.value != Token::Semicolon => Some(self.expression()?),
+            _ => None,
+        };
+        self.terminator()?;
+        Ok(Statement::Return(value))
+    }
+
+    fn terminator(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            None if self.scan_error.is_none() => Ok(()),
+            _ => self.expect(Token::Semicolon),
         }
     }
 
     fn expression(&mut self) -> Result<Expression, Error> {
-        self.equality()
+        Ok(self.node()?.value)
     }
 
-    fn equality(&mut self) -> Result<Expression, Error> {
-        self.binary(
-            Self::comparison,
-            &[
-                (Token::BangEqual, BinaryOperator::NotEqual),
-                (Token::EqualEqual, BinaryOperator::Equal),
-            ],
-        )
+    // Every expression below the statement level carries the span of the
+    // source it was parsed from.
+    fn node(&mut self) -> Result<Spanned<Expression>, Error> {
+        self.nested(Self::comma)
     }
 
-    fn comparison(&mut self) -> Result<Expression, Error> {
-        self.binary(
-            Self::term,
-            &[
-                (Token::Greater, BinaryOperator::Greater),
-                (Token::GreaterEqual, BinaryOperator::GreaterEqual),
-                (Token::Less, BinaryOperator::Less),
-                (Token::LessEqual, BinaryOperator::LessEqual),
-            ],
-        )
+    fn comma(&mut self) -> Result<Spanned<Expression>, Error> {
+        let expression = self.assignment()?;
+        if !self.match_token(Token::Comma) {
+            return Ok(expression);
+        }
+        let start = expression.span;
+        let mut expressions = vec![expression];
+        loop {
+            expressions.push(self.assignment()?);
+            if !self.match_token(Token::Comma) {
+                break;
+            }
+        }
+        Ok(self.spanned(Expression::Comma(expressions), start))
     }
 
-    fn term(&mut self) -> Result<Expression, Error> {
-        self.binary(
-            Self::factor,
-            &[
-                (Token::Minus, BinaryOperator::Sub),
-                (Token::Plus, BinaryOperator::Add),
-            ],
-        )
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth == self.max_depth {
+            return Err(Error::NestingTooDeep);
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
-    fn factor(&mut self) -> Result<Expression, Error> {
-        self.binary(
-            Self::unary,
-            &[
-                (Token::Slash, BinaryOperator::Div),
-                (Token::Star, BinaryOperator::Mul),
-            ],
-        )
+    fn assignment(&mut self) -> Result<Spanned<Expression>, Error> {
+        // An identifier followed by `=` is a plain assignment; anything else
+        // is parsed as an expression and checked as a target afterwards.
+        if let (Some(name), Some(equal)) = (self.peek(), self.peek2()) {
+            if name.value == Token::Identifier && equal.value == Token::Equal {
+                let start = name.span;
+                let name = self.identifier()?;
+                self.next_token();
+                let value = self.nested(Self::assignment)?;
+                let span = start.merge(value.span);
+                return Ok(Spanned {
+                    value: Expression::Assign(name, Box::new(value)),
+                    span,
+                });
+            }
+        }
+        let expression = self.or()?;
+        if self.match_token(Token::Equal) {
+            let value = self.nested(Self::assignment)?;
+            let span = expression.span.merge(value.span);
+            let mut target = expression.value;
+            let value = match &mut target {
+                Expression::Variable(name) => Expression::Assign(*name, Box::new(value)),
+                Expression::Index(object, index) => Expression::SetIndex(
+                    Box::new(object.take()),
+                    Box::new(index.take()),
+                    Box::new(value),
+                ),
+                _ => return Err(Error::InvalidAssignmentTarget),
+            };
+            return Ok(Spanned { value, span });
+        }
+        Ok(expression)
     }
 
-    fn binary<O>(
-        &mut self,
-        mut operand: O,
-        operators: &[(Token, BinaryOperator)],
-    ) -> Result<Expression, Error>
-    where
-        O: FnMut(&mut Self) -> Result<Expression, Error>,
-    {
-        let mut left = operand(self)?;
-        while let Some(operator) = self.match_one_of(operators) {
-            let right = operand(self)?;
-            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+    // Precedence climbing: one frame per operand rather than one per level,
+    // so deeply parenthesized input stays within the nesting limit.
+    fn or(&mut self) -> Result<Spanned<Expression>, Error> {
+        let mut left = self.and()?;
+        while self.match_token(Token::Or) {
+            let right = self.and()?;
+            left = logical(LogicalOperator::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Spanned<Expression>, Error> {
+        let mut left = self.binary(0)?;
+        while self.match_token(Token::And) {
+            let right = self.binary(0)?;
+            left = logical(LogicalOperator::And, left, right);
         }
         Ok(left)
     }
 
-    fn unary(&mut self) -> Result<Expression, Error> {
+    fn binary(&mut self, min_level: usize) -> Result<Spanned<Expression>, Error> {
+        let mut left = self.unary()?;
+        let mut after_comparison = false;
+        while let Some((level, operator)) = self.binary_operator(min_level) {
+            // `1 < 2 < 3` would compare a boolean with a number at runtime.
+            let comparison = is_comparison(operator);
+            if comparison && after_comparison {
+                return Err(Error::ChainedComparison);
+            }
+            after_comparison = comparison;
+            // A left-associative operand only climbs to higher levels, but a
+            // right-associative one recurses once per operator in the chain.
+            let right = match self.precedence.levels[level].associativity {
+                Associativity::Left => self.binary(level + 1)?,
+                Associativity::Right => self.nested(|parser| parser.binary(level))?,
+            };
+            let span = left.span.merge(right.span);
+            left = Spanned {
+                value: Expression::Binary(operator, Box::new(left), Box::new(right)),
+                span,
+            };
+        }
+        Ok(left)
+    }
+
+    fn binary_operator(&mut self, min_level: usize) -> Option<(usize, BinaryOperator)> {
+        let next = self.peek()?;
+        let precedence = self.precedence.clone();
+        let found = precedence
+            .levels
+            .iter()
+            .enumerate()
+            .skip(min_level)
+            .find_map(|(index, level)| {
+                level
+                    .operators
+                    .iter()
+                    .find(|(token, _)| *token == next.value)
+                    .map(|&(_, operator)| (index, operator))
+            });
+        if found.is_some() {
+            self.next_token();
+        }
+        found
+    }
+
+    fn unary(&mut self) -> Result<Spanned<Expression>, Error> {
+        let start = self.peek().map(|token| token.span);
         if let Some(operator) = self.match_one_of(&[
             (Token::Minus, UnaryOperator::Neg),
             (Token::Bang, UnaryOperator::Not),
+            (Token::Plus, UnaryOperator::Pos),
         ]) {
-            let expr = self.unary()?;
-            return Ok(Expression::Unary(operator, Box::new(expr)));
-        }
-        self.primary()
-    }
-
-    fn primary(&mut self) -> Result<Expression, Error> {
-        match self.next_token() {
-            Some(token) => match token.value {
-                Token::Nil => Ok(Expression::Literal(Value::Nil)),
-                Token::True => Ok(Expression::Literal(Value::Boolean(true))),
-                Token::False => Ok(Expression::Literal(Value::Boolean(false))),
-                Token::Number => Ok(Expression::Literal(Value::Number(
-                    self.parse_number(token.span)?,
-                ))),
-                Token::String => Ok(Expression::Literal(Value::String(
-                    self.parse_string(token.span)?,
-                ))),
-                Token::LeftParen => {
-                    let expression = self.expression()?;
-                    self.expect(Token::RightParen)?;
-                    Ok(Expression::Grouping(Box::new(expression)))
-                }
-                _ => Err(Error::ExpectedPrimary),
+            let operand = self.nested(Self::unary)?;
+            let span = operand.span.merge(start.unwrap_or(operand.span));
+            return Ok(Spanned {
+                value: Expression::Unary(operator, Box::new(operand)),
+                span,
+            });
+        }
+        self.power()
+    }
+
+    // `**` binds tighter than a unary operator on its left, so `-2 ** 2` is
+    // `-(2 ** 2)`, but its right operand may be unary, as in `2 ** -1`. It
+    // groups to the right.
+    fn power(&mut self) -> Result<Spanned<Expression>, Error> {
+        let base = self.call()?;
+        if !self.match_token(Token::StarStar) {
+            return Ok(base);
+        }
+        let exponent = self.nested(Self::unary)?;
+        let span = base.span.merge(exponent.span);
+        Ok(Spanned {
+            value: Expression::Binary(BinaryOperator::Pow, Box::new(base), Box::new(exponent)),
+            span,
+        })
+    }
+
+    fn call(&mut self) -> Result<Spanned<Expression>, Error> {
+        let mut expression = self.primary()?;
+        loop {
+            let start = expression.span;
+            if self.match_token(Token::LeftBracket) {
+                let index = self.node()?;
+                self.expect(Token::RightBracket)?;
+                expression = self.spanned(
+                    Expression::Index(Box::new(expression), Box::new(index)),
+                    start,
+                );
+                continue;
+            }
+            if self.match_token(Token::Dot) {
+                let name = self.identifier()?;
+                expression = self.spanned(Expression::Get(Box::new(expression), name), start);
+                continue;
+            }
+            let Some(paren) = self.next_if(Token::LeftParen) else {
+                break;
+            };
+            // Commas separate arguments here, so skip the comma level.
+            let arguments = self.parse_comma_separated(Token::RightParen, |parser| {
+                parser.nested(Self::assignment)
+            })?;
+            if arguments.len() > MAX_ARGUMENTS {
+                return Err(Error::TooManyArguments);
+            }
+            if !self.match_token(Token::RightParen) {
+                return Err(Error::UnclosedCall(paren.span));
+            }
+            expression = self.spanned(Expression::Call(Box::new(expression), arguments), start);
+        }
+        Ok(expression)
+    }
+
+    fn list(&mut self, start: Span) -> Result<Spanned<Expression>, Error> {
+        let elements = self.parse_comma_separated(Token::RightBracket, |parser| {
+            parser.nested(Self::assignment)
+        })?;
+        self.expect(Token::RightBracket)?;
+        Ok(self.spanned(Expression::List(elements), start))
+    }
+
+    fn map(&mut self, start: Span) -> Result<Spanned<Expression>, Error> {
+        let entries = self.parse_comma_separated(Token::RightBrace, |parser| {
+            let key = parser.nested(Self::assignment)?;
+            parser.expect(Token::Colon)?;
+            let value = parser.nested(Self::assignment)?;
+            Ok((key, value))
+        })?;
+        self.expect(Token::RightBrace)?;
+        Ok(self.spanned(Expression::Map(entries), start))
+    }
+
+    // Parses items up to, but not including, `close`, leaving callers to
+    // report a missing one their own way.
+    fn parse_comma_separated<T>(
+        &mut self,
+        close: Token,
+        mut item: impl FnMut(&mut Self) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        if self.peek().is_some_and(|token| token.value == close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if !self.match_token(Token::Comma) {
+                break;
+            }
+            if self.trailing_commas && self.peek().is_some_and(|token| token.value == close) {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn primary(&mut self) -> Result<Spanned<Expression>, Error> {
+        let Some(token) = self.next_token() else {
+            return Err(Error::UnexpectedEof);
+        };
+        let value = match token.value {
+            Token::Nil => Expression::Literal(Value::Nil),
+            Token::True => Expression::Literal(Value::Boolean(true)),
+            Token::False => Expression::Literal(Value::Boolean(false)),
+            Token::Number => Expression::Literal(Value::Number(self.parse_number(token.span)?)),
+            Token::String => Expression::Literal(Value::String(self.parse_string(token.span)?)),
+            Token::Char => Expression::Literal(Value::Number(self.parse_char(token.span)?)),
+            Token::Identifier => Expression::Variable(Symbol::intern(token.lexeme(self.input))),
+            Token::This if self.class_depth == 0 => return Err(Error::ThisOutsideClass),
+            Token::This => Expression::This,
+            Token::Super if self.class_depth == 0 => return Err(Error::SuperOutsideClass),
+            Token::Super => {
+                self.expect(Token::Dot)?;
+                Expression::Super(self.identifier()?)
+            }
+            // The parentheses belong to the span of the grouped expression.
+            Token::LeftParen => {
+                let expression = self.node()?;
+                self.expect(Token::RightParen)?;
+                expression.value
+            }
+            Token::LeftBracket => return self.list(token.span),
+            Token::LeftBrace => return self.map(token.span),
+            _ => return Err(Error::ExpectedPrimary),
+        };
+        Ok(self.spanned(value, token.span))
+    }
+
+    // Spans from `start` to the end of the last consumed token.
+    fn spanned(&self, value: Expression, start: Span) -> Spanned<Expression> {
+        Spanned {
+            value,
+            span: Span {
+                start: start.start,
+                end: self.end,
             },
-            None => Err(Error::ExpectedPrimary),
         }
     }
 
+    fn peek(&mut self) -> Option<Spanned<Token>> {
+        self.peek_nth(0)
+    }
+
+    fn peek2(&mut self) -> Option<Spanned<Token>> {
+        self.peek_nth(1)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<Spanned<Token>> {
+        while self.lookahead.len() <= n && self.scan_error.is_none() {
+            let Spanned { value, span } = self.tokens.next()?;
+            match value {
+                // Comments are skipped here so callers needn't filter them out.
+                Ok(Token::Comment) => {}
+                Ok(value) => self.lookahead.push_back(Spanned { value, span }),
+                Err(value) => self.scan_error = Some(Spanned { value, span }),
+            }
+        }
+        self.lookahead.get(n).copied()
+    }
+
     fn match_one_of<V>(&mut self, values: &[(Token, V)]) -> Option<V>
     where
         V: Copy,
     {
-        self.tokens.peek().copied().and_then(|next| {
+        self.peek().and_then(|next| {
             for (tt, value) in values.iter() {
                 if *tt == next.value {
                     self.next_token();
@@ -147,67 +723,200 @@ where
         })
     }
 
+    fn match_token(&mut self, token: Token) -> bool {
+        self.next_if(token).is_some()
+    }
+
+    fn next_if(&mut self, expected: Token) -> Option<Spanned<Token>> {
+        match self.peek() {
+            Some(token) if token.value == expected => self.next_token(),
+            _ => None,
+        }
+    }
+
+    fn expect_one_of(&mut self, expected: &[Token]) -> Result<(), Error> {
+        match self.peek() {
+            Some(token) if expected.contains(&token.value) => {
+                self.next_token();
+                Ok(())
+            }
+            Some(_) => Err(Error::ExpectedOneOf(expected.to_vec())),
+            None => Err(Error::UnexpectedEof),
+        }
+    }
+
     fn expect(&mut self, expected: Token) -> Result<(), Error> {
-        match self.tokens.peek().copied() {
+        match self.peek() {
             Some(token) if token.value == expected => {
                 self.next_token();
                 Ok(())
             }
-            _ => Err(Error::Expected(expected)),
+            Some(_) => Err(Error::Expected(expected)),
+            None => Err(Error::UnexpectedEof),
         }
     }
 
-    fn parse_number(&self, span: Span) -> Result<f64, Error> {
-        self.input[span.start as usize..span.end as usize]
+    fn identifier(&mut self) -> Result<Symbol, Error> {
+        match self.peek() {
+            Some(token) if token.value == Token::Identifier => {
+                let span = token.span;
+                self.next_token();
+                Ok(Symbol::intern(span.lexeme(self.input)))
+            }
+            Some(_) => Err(Error::Expected(Token::Identifier)),
+            None => Err(Error::UnexpectedEof),
+        }
+    }
+
+    fn parse_number(&mut self, span: Span) -> Result<f64, Error> {
+        // The scanner leaves the dot of `123.` or `1.2.3` out of the number,
+        // but a dot stuck to a number never starts a property access.
+        if self
+            .peek()
+            .is_some_and(|token| token.value == Token::Dot && token.span.start == span.end)
+        {
+            return Err(Error::MalformedNumber);
+        }
+        span.lexeme(self.input)
             .parse()
             .map_err(|_| Error::MalformedNumber)
     }
 
     fn parse_string(&self, span: Span) -> Result<String, Error> {
-        let str = &self.input[span.start as usize..span.end as usize];
-        if !str.ends_with('"') {
-            return Err(Error::MalformedString);
+        self.parse_quoted(span, '"').ok_or(Error::MalformedString)
+    }
+
+    // A character literal is the number of its code point.
+    fn parse_char(&self, span: Span) -> Result<f64, Error> {
+        let contents = self.parse_quoted(span, '\'').ok_or(Error::MalformedChar)?;
+        let mut chars = contents.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Ok(f64::from(u32::from(ch))),
+            _ => Err(Error::MalformedChar),
         }
-        Ok(String::from(
-            &self.input[span.start as usize + 1..span.end as usize - 1],
-        ))
+    }
+
+    // Decodes `\n`, `\t`, `\r`, `\0`, `\"`, `\'` and `\\`. Any other backslash is
+    // kept as written, so a path like "C:\dir" reads as it did before escapes.
+    fn parse_quoted(&self, span: Span, quote: char) -> Option<String> {
+        // A span the scanner didn't produce may not cover a quoted literal.
+        let contents = span
+            .lexeme(self.input)
+            .strip_prefix(quote)
+            .and_then(|str| str.strip_suffix(quote))?;
+        let mut decoded = String::with_capacity(contents.len());
+        let mut chars = contents.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                decoded.push(ch);
+                continue;
+            }
+            let escaped = match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '"' => '"',
+                '\'' => '\'',
+                '\\' => '\\',
+                other => {
+                    decoded.push('\\');
+                    other
+                }
+            };
+            decoded.push(escaped);
+        }
+        Some(decoded)
     }
 
     fn synchronize(&mut self) {
-        while let Some(token) = self.next_token() {
-            if let Token::Semicolon = token.value {
+        // Always make progress, or a statement that fails on its first token
+        // would be retried forever.
+        if self.previous.is_none() {
+            self.next_token();
+        }
+        // The failing token may itself have ended the statement.
+        if self
+            .previous
+            .is_some_and(|token| token.value == Token::Semicolon)
+        {
+            return;
+        }
+        self.skip_statement();
+    }
+
+    // Skips to the start of the next statement.
+    fn skip_statement(&mut self) {
+        while let Some(token) = self.peek() {
+            if starts_statement(token.value) {
                 return;
             }
-            match self.tokens.peek() {
-                Some(token) if starts_statement(token.value) => {
-                    return;
-                }
-                _ => {}
+            self.next_token();
+            if token.value == Token::Semicolon {
+                return;
             }
         }
     }
 
     fn next_token(&mut self) -> Option<Spanned<Token>> {
-        self.tokens.next().map(|token| {
+        self.peek()?;
+        self.lookahead.pop_front().inspect(|token| {
             self.end = token.span.end;
-            token
+            self.previous = Some(*token);
+        })
+    }
+
+    // Whether a statement failed only because the input ended, so that
+    // more of it might complete the statement.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    fn take_scan_error(&mut self) -> Option<Error> {
+        self.scan_error.take().map(|error| match error.value {
+            ScanError::UnexpectedCharacter(ch) => {
+                Error::UnexpectedCharacter(ch.to_string(), error.span)
+            }
+            ScanError::UnterminatedString => Error::UnterminatedString(error.span),
+            ScanError::UnterminatedChar => Error::UnterminatedChar(error.span),
         })
     }
 }
 
 impl<'a, I> Iterator for Parser<'a, I>
 where
-    I: Iterator<Item = Spanned<Token>>,
+    I: Iterator<Item = Spanned<Result<Token, ScanError>>>,
 {
-    type Item = Spanned<Result<Expression, Error>>;
+    type Item = Spanned<Result<Statement, Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.tokens.peek().copied().map(|token| {
+        let token = self.peek();
+        // A lexical error right where a statement would start is reported
+        // on its own.
+        if self.lookahead.is_empty() {
+            if let Some(Spanned { span, .. }) = self.scan_error {
+                let value = self.take_scan_error().map(Err);
+                self.skip_statement();
+                return value.map(|value| Spanned { value, span });
+            }
+        }
+        token.map(|token| {
             let start = token.span.start;
-            let result = self.expression();
-            if result.is_err() {
+            self.previous = None;
+            let result = self.declaration().map_err(|error| {
+                let error = self.take_scan_error().unwrap_or(error);
+                self.incomplete |= match error {
+                    Error::UnexpectedEof => true,
+                    Error::UnclosedCall(_) => self.peek().is_none(),
+                    _ => false,
+                };
                 self.synchronize();
-            }
+                error
+            });
+            let result = match self.duplicate_local.take() {
+                Some(name) if result.is_ok() => Err(Error::DuplicateLocal(name)),
+                _ => result,
+            };
             Spanned {
                 value: result,
                 span: Span {
@@ -219,6 +928,27 @@ where
     }
 }
 
+// Parses the whole of `source` as a single expression, e.g. for a calculator.
+pub fn parse_expression(source: &str) -> Result<Expression, Spanned<Error>> {
+    let mut parser = Parser::new(source, Scanner::new(source));
+    let result = parser.node().and_then(|expression| match parser.peek() {
+        Some(token) => Err(Error::TrailingInput(token.span)),
+        // Replaced by the pending lexical error below.
+        None if parser.scan_error.is_some() => Err(Error::UnexpectedEof),
+        None => Ok(expression.value),
+    });
+    result.map_err(|error| {
+        let error = parser.take_scan_error().unwrap_or(error);
+        Spanned {
+            span: error.span().unwrap_or(Span {
+                start: 0,
+                end: parser.end,
+            }),
+            value: error,
+        }
+    })
+}
+
 fn starts_statement(token: Token) -> bool {
     matches!(
         token,
@@ -232,3 +962,913 @@ fn starts_statement(token: Token) -> bool {
             | Token::Return
     )
 }
+
+fn logical(operator: LogicalOperator, left: Node, right: Node) -> Node {
+    Spanned {
+        span: left.span.merge(right.span),
+        value: Expression::Logical(operator, Box::new(left), Box::new(right)),
+    }
+}
+
+fn is_comparison(operator: BinaryOperator) -> bool {
+    matches!(
+        operator,
+        BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual
+            | BinaryOperator::Less
+            | BinaryOperator::LessEqual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval, precedence::Level, scanner::Scanner};
+
+    fn parse(source: &str) -> Vec<Result<Statement, Error>> {
+        Parser::new(source, Scanner::new(source))
+            .map(|statement| statement.value)
+            .collect()
+    }
+
+    #[test]
+    fn empty_statements() {
+        let statements = parse("print 1;; ;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Ok(Statement::Print(Expression::Literal(Value::Number(_)))),
+                Ok(Statement::Empty),
+                Ok(Statement::Empty),
+            ]
+        ));
+    }
+
+    #[test]
+    fn empty_input() {
+        for source in ["", " \t\r\n  \n", "// one\n// two", "  // trailing"] {
+            assert!(parse(source).is_empty(), "{:?}", source);
+        }
+        assert!(matches!(
+            parse("// first\nprint 1; // second\n")[..],
+            [Ok(Statement::Print(_))]
+        ));
+        // Recovery skips over comments too.
+        assert!(matches!(
+            parse(") // oops\nprint 1;")[..],
+            [Err(Error::ExpectedPrimary), Ok(Statement::Print(_))]
+        ));
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        let statements = parse("1 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::Expected(Token::Semicolon))]
+        ));
+    }
+
+    #[test]
+    fn nested_calls() {
+        let statements = parse("f(g(x), 1);");
+        let [Ok(Statement::Expression(Expression::Call(callee, arguments)))] =
+            statements.as_slice()
+        else {
+            panic!("expected a call, got {:?}", statements);
+        };
+        assert!(matches!(&callee.value, Expression::Variable(name) if name.as_str() == "f"));
+        assert!(matches!(
+            arguments.as_slice(),
+            [
+                Spanned { value: Expression::Call(_, inner), .. },
+                Spanned { value: Expression::Literal(Value::Number(_)), .. },
+            ] if matches!(inner.as_slice(), [Spanned { value: Expression::Variable(_), .. }])
+        ));
+    }
+
+    #[test]
+    fn unclosed_call() {
+        let statements = parse("f(g(x), 1");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::UnclosedCall(Span { start: 1, end: 2 }))]
+        ));
+        let statements = parse("f(g(x);");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::UnclosedCall(Span { start: 1, end: 2 }))]
+        ));
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        let arguments = vec!["1"; MAX_ARGUMENTS].join(", ");
+        let statements = parse(&format!("f({});", arguments));
+        assert!(matches!(statements.as_slice(), [Ok(_)]));
+        let arguments = vec!["1"; MAX_ARGUMENTS + 1].join(", ");
+        let statements = parse(&format!("f({});", arguments));
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::TooManyArguments)]
+        ));
+    }
+
+    #[test]
+    fn invalid_assignment_target() {
+        let statements = parse("1 = 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::InvalidAssignmentTarget)]
+        ));
+    }
+
+    #[test]
+    fn logical_operators() {
+        let source = "a = b or c and d < 1 or e;";
+        let [Ok(Statement::Expression(Expression::Assign(_, value)))] = &parse(source)[..] else {
+            panic!("expected an assignment");
+        };
+        let Expression::Logical(LogicalOperator::Or, left, right) = &value.value else {
+            panic!("expected `or` at the top, got {:?}", value);
+        };
+        assert!(matches!(right.value, Expression::Variable(_)));
+        assert!(matches!(
+            &left.value,
+            Expression::Logical(LogicalOperator::Or, _, and)
+                if matches!(and.value, Expression::Logical(LogicalOperator::And, _, _))
+        ));
+        assert_eq!(left.span.lexeme(source), "b or c and d < 1");
+    }
+
+    #[test]
+    fn chained_comparison() {
+        for source in ["1 < 2 < 3;", "a >= b + 1 <= c;", "x == 1 < 2 > 3;"] {
+            assert!(
+                matches!(parse(source).as_slice(), [Err(Error::ChainedComparison)]),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(
+            parse("1 < 2 == 3 < 4; (1 < 2) < 3;").as_slice(),
+            [Ok(_), Ok(_)]
+        ));
+    }
+
+    #[test]
+    fn return_outside_function() {
+        let statements = parse("return 1;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::ReturnOutsideFunction)]
+        ));
+    }
+
+    fn parse_expression(parser: Parser<'_, Scanner<'_>>) -> Expression {
+        match parser.map(|statement| statement.value).next() {
+            Some(Ok(Statement::Expression(expression))) => expression,
+            statement => panic!("expected an expression, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn single_expression() {
+        let expression = super::parse_expression("(1 + 2) * x // comment").unwrap();
+        assert_eq!(crate::ast::unparse(&expression), "(1 + 2) * x");
+        assert!(super::parse_expression("a, b = 2").is_ok());
+        for (source, start, end) in [
+            ("1 + 2 3", 6, 7),
+            ("1 + 2;", 5, 6),
+            ("f(x) ) + 1", 5, 6),
+            ("1 @ 2", 2, 3),
+            ("1 + 'ab", 4, 7),
+        ] {
+            let error = super::parse_expression(source).unwrap_err();
+            assert_eq!(
+                (error.span.start, error.span.end),
+                (start, end),
+                "{}: {}",
+                source,
+                error.value
+            );
+        }
+        assert!(matches!(
+            super::parse_expression("1 2").unwrap_err().value,
+            Error::TrailingInput(_)
+        ));
+        assert!(matches!(
+            super::parse_expression("").unwrap_err().value,
+            Error::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn trailing_commas() {
+        let parse_forgiving = |source| {
+            Parser::with_trailing_commas(source, Scanner::new(source))
+                .map(|statement| statement.value)
+                .collect::<Vec<_>>()
+        };
+        for source in [
+            "[1, 2,];",
+            "f(a, b,);",
+            "var m = {1: 2, 3: 4,};",
+            "fun f(a, b,) {}",
+        ] {
+            assert!(
+                matches!(parse_forgiving(source).as_slice(), [Ok(_)]),
+                "{}",
+                source
+            );
+            assert!(matches!(parse(source).as_slice(), [Err(_)]), "{}", source);
+        }
+        for source in ["[1];", "[];", "f();"] {
+            assert!(
+                matches!(parse_forgiving(source).as_slice(), [Ok(_)]),
+                "{}",
+                source
+            );
+        }
+        let source = "[1, 2,];";
+        let Some(Ok(Statement::Expression(Expression::List(elements)))) =
+            &parse_forgiving(source).pop()
+        else {
+            panic!("expected a list");
+        };
+        assert_eq!(elements.len(), 2);
+        for source in ["[1, 2,,];", "f(a,,);", "[,];", "fun f(a,,) {}"] {
+            assert!(
+                matches!(parse_forgiving(source).first(), Some(Err(_))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn power_binds_tighter_than_unary() {
+        for (source, expected) in [
+            ("-2 ** 2", -4.0),
+            ("(-2) ** 2", 4.0),
+            ("2 ** 3 ** 2", 512.0),
+            ("2 ** -1", 0.5),
+            ("2 * 3 ** 2", 18.0),
+        ] {
+            let expression = parse_expression(Parser::new(source, Scanner::new(source)));
+            assert_eq!(
+                eval(expression).unwrap(),
+                Value::Number(expected),
+                "{}",
+                source
+            );
+        }
+        let source = "-2 ** 2";
+        let expression = parse_expression(Parser::new(source, Scanner::new(source)));
+        assert!(matches!(
+            &expression,
+            Expression::Unary(UnaryOperator::Neg, operand)
+                if matches!(operand.value, Expression::Binary(BinaryOperator::Pow, ..))
+        ));
+    }
+
+    #[test]
+    fn expression_spans() {
+        let source = "a = -(1 + x) * f(y)[0]";
+        let Expression::Assign(_, value) =
+            &parse_expression(Parser::new(source, Scanner::new(source)))
+        else {
+            panic!("expected an assignment");
+        };
+        let lexeme = |node: &Spanned<Expression>| node.span.lexeme(source);
+        assert_eq!(lexeme(value), "-(1 + x) * f(y)[0]");
+        let Expression::Binary(_, left, right) = &value.value else {
+            panic!("expected a multiplication, got {:?}", value);
+        };
+        assert_eq!(lexeme(left), "-(1 + x)");
+        assert_eq!(lexeme(right), "f(y)[0]");
+        let Expression::Unary(_, operand) = &left.value else {
+            panic!("expected a negation, got {:?}", left);
+        };
+        assert_eq!(lexeme(operand), "(1 + x)");
+        let Expression::Index(call, index) = &right.value else {
+            panic!("expected an index, got {:?}", right);
+        };
+        assert_eq!(lexeme(call), "f(y)");
+        assert_eq!(lexeme(index), "0");
+    }
+
+    #[test]
+    fn default_precedence() {
+        let source = "2 + 3 * 4";
+        let expression = parse_expression(Parser::new(source, Scanner::new(source)));
+        assert!(matches!(
+            &expression,
+            Expression::Binary(BinaryOperator::Add, _, right)
+                if matches!(&right.value, Expression::Binary(BinaryOperator::Mul, _, _))
+        ));
+        assert_eq!(eval(expression).unwrap(), Value::Number(14.0));
+    }
+
+    #[test]
+    fn custom_precedence() {
+        let mut precedence = Precedence::default();
+        let factor = precedence.levels.len() - 1;
+        precedence.levels.swap(factor - 1, factor);
+        let source = "2 + 3 * 4";
+        let expression = parse_expression(Parser::with_precedence(
+            source,
+            Scanner::new(source),
+            precedence,
+        ));
+        assert!(matches!(
+            &expression,
+            Expression::Binary(BinaryOperator::Mul, left, _)
+                if matches!(&left.value, Expression::Binary(BinaryOperator::Add, _, _))
+        ));
+        assert_eq!(eval(expression).unwrap(), Value::Number(20.0));
+    }
+
+    #[test]
+    fn right_associative_level() {
+        let mut precedence = Precedence::default();
+        let term = precedence.levels.len() - 2;
+        precedence.levels[term] = Level::right(&[(Token::Minus, BinaryOperator::Sub)]);
+        let source = "8 - 4 - 2";
+        let expression = parse_expression(Parser::with_precedence(
+            source,
+            Scanner::new(source),
+            precedence,
+        ));
+        assert_eq!(eval(expression).unwrap(), Value::Number(6.0));
+
+        let mut precedence = Precedence::default();
+        precedence.levels[term] = Level::right(&[(Token::Minus, BinaryOperator::Sub)]);
+        let source = "1 - ".repeat(200_000) + "1;";
+        let mut parser = Parser::with_precedence(&source, Scanner::new(&source), precedence);
+        assert!(matches!(
+            parser.next().map(|statement| statement.value),
+            Some(Err(Error::NestingTooDeep))
+        ));
+    }
+
+    #[test]
+    fn calculator_dialect() {
+        let source = "var and = 1; and + 2 < 4;";
+        let precedence = Precedence::default().without(&[
+            BinaryOperator::Less,
+            BinaryOperator::LessEqual,
+            BinaryOperator::Greater,
+            BinaryOperator::GreaterEqual,
+        ]);
+        let statements = Parser::with_precedence(
+            source,
+            Scanner::without_keywords(source, &[Token::And]),
+            precedence,
+        )
+        .map(|statement| statement.value)
+        .collect::<Vec<_>>();
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Ok(Statement::Var(name, _)),
+                Err(Error::Expected(Token::Semicolon)),
+            ] if name.as_str() == "and"
+        ));
+        // The default scanner still reserves `and`.
+        assert!(matches!(
+            parse("var and = 1;").as_slice(),
+            [Err(Error::Expected(Token::Identifier))]
+        ));
+    }
+
+    #[test]
+    fn loop_control_outside_loop() {
+        assert!(matches!(
+            parse("break;").as_slice(),
+            [Err(Error::BreakOutsideLoop)]
+        ));
+        assert!(matches!(
+            parse("continue;").as_slice(),
+            [Err(Error::ContinueOutsideLoop)]
+        ));
+        assert!(matches!(
+            parse("while (true) { fun f() { break; } }").first(),
+            Some(Err(Error::BreakOutsideLoop))
+        ));
+        assert!(matches!(
+            parse("for (;;) { if (true) break; else continue; }").as_slice(),
+            [Ok(Statement::For(None, None, None, _))]
+        ));
+    }
+
+    #[test]
+    fn this_and_super_outside_class() {
+        assert!(matches!(
+            parse("print this;").as_slice(),
+            [Err(Error::ThisOutsideClass)]
+        ));
+        assert!(matches!(
+            parse("fun f() { return this.x; }").first(),
+            Some(Err(Error::ThisOutsideClass))
+        ));
+        assert!(matches!(
+            parse("super.method();").as_slice(),
+            [Err(Error::SuperOutsideClass)]
+        ));
+        assert!(matches!(
+            parse("var a = super; print 1;").as_slice(),
+            [Err(Error::SuperOutsideClass), Ok(Statement::Print(_))]
+        ));
+    }
+
+    #[test]
+    fn error_messages() {
+        let messages = [
+            (Error::ExpectedPrimary, "expected expression"),
+            (Error::Expected(Token::RightParen), "expected ')'"),
+            (Error::Expected(Token::Identifier), "expected identifier"),
+            (Error::Expected(Token::While), "expected keyword 'while'"),
+            (
+                Error::ExpectedOneOf(vec![Token::Semicolon, Token::RightBrace]),
+                "expected ';' or '}'",
+            ),
+            (
+                Error::ExpectedOneOf(vec![Token::Comma, Token::Semicolon, Token::RightBrace]),
+                "expected ',', ';' or '}'",
+            ),
+            (
+                Error::UnclosedCall(Span { start: 0, end: 1 }),
+                "unclosed '(' in call",
+            ),
+            (
+                Error::UnexpectedCharacter(String::from("@"), Span { start: 0, end: 1 }),
+                "unexpected character '@'",
+            ),
+            (
+                Error::UnterminatedString(Span { start: 0, end: 1 }),
+                "unterminated string",
+            ),
+            (
+                Error::UnterminatedChar(Span { start: 0, end: 1 }),
+                "unterminated character literal",
+            ),
+            (
+                Error::MalformedChar,
+                "malformed character literal; it must hold exactly one character",
+            ),
+            (
+                Error::TrailingInput(Span { start: 0, end: 1 }),
+                "expected end of input",
+            ),
+            (
+                Error::DuplicateLocal(Symbol::intern("a")),
+                "'a' is already declared in this scope",
+            ),
+            (
+                Error::TooManyArguments,
+                "can't have more than 255 arguments",
+            ),
+            (
+                Error::TooManyParameters,
+                "can't have more than 255 parameters",
+            ),
+            (Error::InvalidAssignmentTarget, "invalid assignment target"),
+            (
+                Error::ReturnOutsideFunction,
+                "can't return from top-level code",
+            ),
+            (
+                Error::BreakOutsideLoop,
+                "can't use 'break' outside of a loop",
+            ),
+            (
+                Error::ContinueOutsideLoop,
+                "can't use 'continue' outside of a loop",
+            ),
+            (
+                Error::ThisOutsideClass,
+                "can't use 'this' outside of a class",
+            ),
+            (
+                Error::SuperOutsideClass,
+                "can't use 'super' outside of a class",
+            ),
+            (Error::MalformedNumber, "malformed number literal"),
+            (Error::MalformedString, "malformed string literal"),
+            (Error::NestingTooDeep, "expression is nested too deeply"),
+            (Error::UnexpectedEof, "unexpected end of input"),
+            (
+                Error::ChainedComparison,
+                "comparisons can't be chained; join them instead, as in 'a < b and b < c'",
+            ),
+        ];
+        for (error, message) in messages {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn missing_terminator_in_block() {
+        for source in ["{ print 1 print 2; }", "{ print 1; print 2 }"] {
+            assert!(
+                matches!(
+                    parse(source).first(),
+                    Some(Err(Error::Expected(Token::Semicolon)))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn missing_parameter_separator() {
+        let statements = parse("fun f(a b) {}");
+        assert!(matches!(
+            statements.first(),
+            Some(Err(Error::ExpectedOneOf(tokens)))
+                if tokens == &[Token::Comma, Token::RightParen]
+        ));
+    }
+
+    #[test]
+    fn parentheses_leave_no_node() {
+        let source = "(1 + 2) * 3";
+        let expression = parse_expression(Parser::new(source, Scanner::new(source)));
+        let Expression::Binary(BinaryOperator::Mul, left, right) = &expression else {
+            panic!("expected a multiplication, got {:?}", expression);
+        };
+        assert!(matches!(
+            &left.value,
+            Expression::Binary(BinaryOperator::Add, _, _)
+        ));
+        assert!(matches!(
+            &right.value,
+            Expression::Literal(Value::Number(_))
+        ));
+        assert_eq!(eval(expression).unwrap(), Value::Number(9.0));
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let statements = parse("1 @ 2; print 3;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Err(Error::UnexpectedCharacter(lexeme, Span { start: 2, end: 3 })),
+                Ok(Statement::Print(_)),
+            ] if lexeme == "@"
+        ));
+        let statements = parse("print (1 + #); print 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Err(Error::UnexpectedCharacter(lexeme, Span { start: 11, end: 12 })),
+                Ok(Statement::Print(_)),
+            ] if lexeme == "#"
+        ));
+        let statements = parse("print 1; @ print 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Ok(Statement::Print(_)),
+                Err(Error::UnexpectedCharacter(lexeme, Span { start: 9, end: 10 })),
+                Ok(Statement::Print(_)),
+            ] if lexeme == "@"
+        ));
+    }
+
+    #[test]
+    fn unterminated_string_statement() {
+        let statements = parse("print 1; print \"oops");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Ok(Statement::Print(_)),
+                Err(Error::UnterminatedString(Span { start: 15, end: 20 })),
+            ]
+        ));
+    }
+
+    #[test]
+    fn duplicate_locals() {
+        for source in [
+            "var a = 1; var a = 2;",
+            "fun f() {} var f;",
+            "{ var a; { var a; } }",
+            "{ var a; } { var a; }",
+            "fun f(a) { { var a; } }",
+            "{ var i; for (var i = 0; i < 1; i = i + 1) { var i; } }",
+        ] {
+            assert!(parse(source).iter().all(Result::is_ok), "{}", source);
+        }
+        for source in [
+            "{ var a = 1; var a = 2; }",
+            "{ var a; fun a() {} }",
+            "fun f(a, b, a) {}",
+            "fun f(a) { var a; }",
+        ] {
+            assert!(
+                matches!(parse(source).as_slice(), [Err(Error::DuplicateLocal(name))] if name.as_str() == "a"),
+                "{}",
+                source
+            );
+        }
+        // Only the duplicate is rejected, and parsing carries on after it.
+        assert!(matches!(
+            parse("{ var a; var a; } var a; { var a; }").as_slice(),
+            [Err(Error::DuplicateLocal(_)), Ok(_), Ok(_)]
+        ));
+    }
+
+    #[test]
+    fn strings_at_end_of_input() {
+        for (source, expected) in [
+            ("\"abc\"", "abc"),
+            ("\"\"", ""),
+            ("\"a\\\"\"", "a\""),
+            ("\"é\"", "é"),
+        ] {
+            let expression = super::parse_expression(source).unwrap();
+            assert_eq!(
+                expression,
+                Expression::Literal(Value::String(String::from(expected))),
+                "{}",
+                source
+            );
+        }
+        for source in ["\"abc", "\"", "\"a\\\"", "\"é"] {
+            let error = super::parse_expression(source).unwrap_err();
+            assert!(
+                matches!(error.value, Error::UnterminatedString(_)),
+                "{}",
+                source
+            );
+            // The span runs to the end of the input without losing any of it.
+            assert_eq!(error.span.lexeme(source), source);
+        }
+    }
+
+    #[test]
+    fn char_literals() {
+        for (source, expected) in [
+            ("'a';", 97.0),
+            ("'\\n';", 10.0),
+            ("'\\'';", 39.0),
+            ("'é';", 233.0),
+        ] {
+            assert!(
+                matches!(
+                    parse(source).as_slice(),
+                    [Ok(Statement::Expression(Expression::Literal(Value::Number(n))))] if *n == expected
+                ),
+                "{}",
+                source
+            );
+        }
+        for source in ["'';", "'ab';", "'\\q';"] {
+            assert!(
+                matches!(parse(source).as_slice(), [Err(Error::MalformedChar)]),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(
+            parse("'a").as_slice(),
+            [Err(Error::UnterminatedChar(Span { start: 0, end: 2 }))]
+        ));
+    }
+
+    #[test]
+    fn malformed_literal_spans() {
+        let source = "é\"1.5";
+        let token = |value, start, end| Spanned {
+            value: Ok(value),
+            span: Span { start, end },
+        };
+        for (value, start, end) in [
+            (Token::String, 0, 3),
+            (Token::String, 1, 3),
+            (Token::String, 2, 3),
+            (Token::String, -4, 40),
+            (Token::Number, 1, 4),
+            (Token::Number, 1, 2),
+            (Token::Number, 9, 12),
+        ] {
+            let statement = Parser::new(source, [token(value, start, end)].into_iter())
+                .next()
+                .unwrap()
+                .value;
+            assert!(
+                matches!(
+                    statement,
+                    Err(Error::MalformedString | Error::MalformedNumber)
+                ),
+                "{:?} at {}..{} gave {:?}",
+                value,
+                start,
+                end,
+                statement
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        let source = "(".repeat(50_000);
+        assert!(matches!(
+            parse(&source).first(),
+            Some(Err(Error::NestingTooDeep))
+        ));
+        let source = "-".repeat(50_000) + "1;";
+        assert!(matches!(
+            parse(&source).first(),
+            Some(Err(Error::NestingTooDeep))
+        ));
+    }
+
+    #[test]
+    fn deeply_nested_statements() {
+        for source in [
+            "a = ".repeat(200_000) + "1;",
+            "a = 2 ** ".repeat(100_000) + "1;",
+            "x[0] = ".repeat(15_000) + "1;",
+            "{".repeat(200_000),
+            "if (true) ".repeat(200_000),
+            "while (true) ".repeat(9_000),
+            "for (;;) ".repeat(9_000),
+            "fun f() {".repeat(9_000),
+        ] {
+            assert!(
+                matches!(parse(&source).first(), Some(Err(Error::NestingTooDeep))),
+                "{}",
+                &source[..20]
+            );
+        }
+    }
+
+    #[test]
+    fn custom_max_depth() {
+        let source = "((1));";
+        let mut parser = Parser::with_max_depth(source, Scanner::new(source), 2);
+        assert!(matches!(
+            parser.next().map(|statement| statement.value),
+            Some(Err(Error::NestingTooDeep))
+        ));
+        let mut parser = Parser::with_max_depth(source, Scanner::new(source), 3);
+        assert!(matches!(
+            parser.next().map(|statement| statement.value),
+            Some(Ok(_))
+        ));
+    }
+
+    #[test]
+    fn comma_expression() {
+        let statements = parse("1, 2, 3;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Ok(Statement::Expression(Expression::Comma(expressions)))] if expressions.len() == 3
+        ));
+        let statements = parse("f(1, 2);");
+        assert!(matches!(
+            statements.as_slice(),
+            [Ok(Statement::Expression(Expression::Call(_, arguments)))] if arguments.len() == 2
+        ));
+    }
+
+    #[test]
+    fn synchronize_keeps_next_statement() {
+        let statements = parse("var x = 1 print 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Err(Error::Expected(Token::Semicolon)),
+                Ok(Statement::Print(_))
+            ]
+        ));
+        let statements = parse("var = 1; print 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                Err(Error::Expected(Token::Identifier)),
+                Ok(Statement::Print(_))
+            ]
+        ));
+        let statements = parse("1 +; print 2;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::ExpectedPrimary), Ok(Statement::Print(_))]
+        ));
+        let statements = parse(") print 3;");
+        assert!(matches!(
+            statements.as_slice(),
+            [Err(Error::ExpectedPrimary), Ok(Statement::Print(_))]
+        ));
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        assert!(matches!(
+            parse("123.;").as_slice(),
+            [Err(Error::MalformedNumber)]
+        ));
+        assert!(matches!(
+            parse("1.2.3;").as_slice(),
+            [Err(Error::MalformedNumber)]
+        ));
+        assert!(matches!(
+            parse(".5;").as_slice(),
+            [Err(Error::ExpectedPrimary)]
+        ));
+        assert!(matches!(
+            parse("1.25;").as_slice(),
+            [Ok(Statement::Expression(Expression::Literal(Value::Number(n))))] if *n == 1.25
+        ));
+        assert!(matches!(
+            parse("1.x;").as_slice(),
+            [Err(Error::MalformedNumber)]
+        ));
+        assert!(matches!(
+            parse("1 .x;").as_slice(),
+            [Ok(Statement::Expression(Expression::Get(..)))]
+        ));
+    }
+
+    #[test]
+    fn property_access() {
+        let statements = parse("a.b.c(1).d;");
+        let [Ok(Statement::Expression(Expression::Get(object, d)))] = statements.as_slice() else {
+            panic!("expected a property access, got {:?}", statements);
+        };
+        assert_eq!(d.as_str(), "d");
+        let Expression::Call(callee, _) = &object.value else {
+            panic!("expected a call, got {:?}", object);
+        };
+        assert!(matches!(
+            &callee.value,
+            Expression::Get(inner, c)
+                if c.as_str() == "c" && matches!(&inner.value, Expression::Get(_, b) if b.as_str() == "b")
+        ));
+        assert!(matches!(
+            parse("a.1;").as_slice(),
+            [Err(Error::Expected(Token::Identifier))]
+        ));
+    }
+
+    #[test]
+    fn string_literals() {
+        let statements = parse(r#""plain"; "tab\there"; "quote \" and \\"; "C:\dir";"#);
+        let strings = statements
+            .iter()
+            .map(|statement| match statement {
+                Ok(Statement::Expression(Expression::Literal(Value::String(string)))) => {
+                    string.as_str()
+                }
+                statement => panic!("expected a string, got {:?}", statement),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            strings,
+            ["plain", "tab\there", "quote \" and \\", "C:\\dir"]
+        );
+    }
+
+    #[test]
+    fn two_token_lookahead() {
+        let source = "a = 1";
+        let mut parser = Parser::new(source, Scanner::new(source));
+        assert_eq!(parser.peek2().map(|token| token.value), Some(Token::Equal));
+        assert_eq!(
+            parser.peek().map(|token| token.value),
+            Some(Token::Identifier)
+        );
+        assert!(matches!(
+            parser.next().map(|statement| statement.value),
+            Some(Ok(Statement::Expression(Expression::Assign(name, _)))) if name.as_str() == "a"
+        ));
+        assert!(parser.peek2().is_none());
+
+        assert!(matches!(
+            parse("a == b;").as_slice(),
+            [Ok(Statement::Expression(Expression::Binary(
+                BinaryOperator::Equal,
+                _,
+                _
+            )))]
+        ));
+        assert!(matches!(
+            parse("a = b = c;").as_slice(),
+            [Ok(Statement::Expression(Expression::Assign(_, value)))]
+                if matches!(&value.value, Expression::Assign(..))
+        ));
+        assert!(matches!(
+            parse("(a) = 1;").as_slice(),
+            [Ok(Statement::Expression(Expression::Assign(..)))]
+        ));
+        assert!(matches!(
+            parse("a + b = 1;").as_slice(),
+            [Err(Error::InvalidAssignmentTarget)]
+        ));
+    }
+}
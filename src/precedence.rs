@@ -0,0 +1,83 @@
+use crate::{ast::BinaryOperator, scanner::Token};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct Level {
+    pub operators: Vec<(Token, BinaryOperator)>,
+    pub associativity: Associativity,
+}
+
+impl Level {
+    pub fn left(operators: &[(Token, BinaryOperator)]) -> Self {
+        Self {
+            operators: operators.to_vec(),
+            associativity: Associativity::Left,
+        }
+    }
+
+    pub fn right(operators: &[(Token, BinaryOperator)]) -> Self {
+        Self {
+            operators: operators.to_vec(),
+            associativity: Associativity::Right,
+        }
+    }
+}
+
+// Binary operator levels, from the loosest binding to the tightest.
+#[derive(Clone, Debug)]
+pub struct Precedence {
+    pub levels: Vec<Level>,
+}
+
+impl Precedence {
+    // Drops `operators`, and any level left empty, so that their tokens no
+    // longer continue an expression.
+    pub fn without(mut self, operators: &[BinaryOperator]) -> Self {
+        for level in &mut self.levels {
+            level
+                .operators
+                .retain(|(_, operator)| !operators.contains(operator));
+        }
+        self.levels.retain(|level| !level.operators.is_empty());
+        self
+    }
+}
+
+impl Default for Precedence {
+    fn default() -> Self {
+        Self {
+            levels: vec![
+                Level::left(&[
+                    (Token::BangEqual, BinaryOperator::NotEqual),
+                    (Token::EqualEqual, BinaryOperator::Equal),
+                ]),
+                Level::left(&[(Token::Pipe, BinaryOperator::BitOr)]),
+                Level::left(&[(Token::Caret, BinaryOperator::BitXor)]),
+                Level::left(&[(Token::Ampersand, BinaryOperator::BitAnd)]),
+                Level::left(&[
+                    (Token::LessLess, BinaryOperator::ShiftLeft),
+                    (Token::GreaterGreater, BinaryOperator::ShiftRight),
+                ]),
+                Level::left(&[
+                    (Token::Greater, BinaryOperator::Greater),
+                    (Token::GreaterEqual, BinaryOperator::GreaterEqual),
+                    (Token::Less, BinaryOperator::Less),
+                    (Token::LessEqual, BinaryOperator::LessEqual),
+                ]),
+                Level::left(&[
+                    (Token::Minus, BinaryOperator::Sub),
+                    (Token::Plus, BinaryOperator::Add),
+                ]),
+                Level::left(&[
+                    (Token::Slash, BinaryOperator::Div),
+                    (Token::Star, BinaryOperator::Mul),
+                ]),
+            ],
+        }
+    }
+}
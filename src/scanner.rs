@@ -1,4 +1,7 @@
-use std::str::Chars;
+use std::{
+    fmt::{self, Display},
+    str::Chars,
+};
 
 use crate::span::{Span, Spanned};
 
@@ -9,13 +12,20 @@ pub enum Token {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // One or two character tokens.
     Bang,
@@ -26,15 +36,20 @@ pub enum Token {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
 
     // Literals.
     Identifier,
     String,
+    Char,
     Number,
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -52,21 +67,117 @@ pub enum Token {
 
     // Comments.
     Comment,
+}
+
+impl Token {
+    // How diagnostics refer to the token: symbols as themselves, keywords
+    // as `keyword 'while'`, and everything else by what it is.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Dot => ".",
+            Token::Minus => "-",
+            Token::Plus => "+",
+            Token::Semicolon => ";",
+            Token::Slash => "/",
+            Token::Star => "*",
+            Token::StarStar => "**",
+            Token::Ampersand => "&",
+            Token::Pipe => "|",
+            Token::Caret => "^",
+            Token::Bang => "!",
+            Token::BangEqual => "!=",
+            Token::Equal => "=",
+            Token::EqualEqual => "==",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::LessLess => "<<",
+            Token::GreaterGreater => ">>",
+            Token::Identifier => "identifier",
+            Token::String => "string",
+            Token::Char => "character",
+            Token::Number => "number",
+            Token::And => "keyword 'and'",
+            Token::Break => "keyword 'break'",
+            Token::Class => "keyword 'class'",
+            Token::Continue => "keyword 'continue'",
+            Token::Else => "keyword 'else'",
+            Token::False => "keyword 'false'",
+            Token::Fun => "keyword 'fun'",
+            Token::For => "keyword 'for'",
+            Token::If => "keyword 'if'",
+            Token::Nil => "keyword 'nil'",
+            Token::Or => "keyword 'or'",
+            Token::Print => "keyword 'print'",
+            Token::Return => "keyword 'return'",
+            Token::Super => "keyword 'super'",
+            Token::This => "keyword 'this'",
+            Token::True => "keyword 'true'",
+            Token::Var => "keyword 'var'",
+            Token::While => "keyword 'while'",
+            Token::Comment => "comment",
+        }
+    }
+}
 
-    // Unknown.
-    Unknown,
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name();
+        // Symbols are quoted so that they stand out in a sentence.
+        if name.chars().all(|ch| ch.is_ascii_punctuation()) {
+            write!(f, "'{}'", name)
+        } else {
+            f.write_str(name)
+        }
+    }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ScanError {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    UnterminatedChar,
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::UnexpectedCharacter(ch) => write!(f, "unexpected character '{}'", ch),
+            ScanError::UnterminatedString => write!(f, "unterminated string"),
+            ScanError::UnterminatedChar => write!(f, "unterminated character literal"),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+#[derive(Clone)]
 pub struct Scanner<'a> {
     input: &'a str,
     chars: Chars<'a>,
+    // Keywords scanned as plain identifiers instead.
+    disabled_keywords: &'a [Token],
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::without_keywords(input, &[])
+    }
+
+    pub fn without_keywords(input: &'a str, disabled_keywords: &'a [Token]) -> Self {
         Self {
             input,
             chars: input.chars(),
+            disabled_keywords,
         }
     }
 
@@ -102,100 +213,361 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        self.chars.clone().next().unwrap_or('\0')
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> char {
+        self.chars.as_str().chars().nth(n).unwrap_or('\0')
     }
 
     fn current_index(&self) -> usize {
         self.input.len() - self.chars.as_str().len()
     }
 
+    // A fraction needs a digit after the dot, so `123.` is `Number` `Dot`, which
+    // the parser rejects. A leading dot is not part of a number: `.5` is `Dot`
+    // `Number`.
     fn number(&mut self) -> Token {
-        if let Some('.') = self.consume_while(|ch| ch.is_ascii_digit() || ch == '.') {
+        self.consume_while(|ch| ch.is_ascii_digit());
+        if self.peek() == '.' && self.peek_nth(1).is_ascii_digit() {
+            self.next_char();
             self.consume_while(|ch| ch.is_ascii_digit());
         }
         Token::Number
     }
 
     fn comment(&mut self) -> Token {
-        self.consume_while(|ch| ch != '\n');
+        self.consume_while(|ch| ch != '\n' && ch != '\r');
         Token::Comment
     }
 
-    fn string(&mut self) -> Token {
-        if self.consume_while(|ch| ch != '"').is_some() {
-            self.next_char();
+    // Scans the rest of a string or character literal. The contents are
+    // checked by the parser.
+    fn quoted(&mut self, quote: char) -> Result<Token, ScanError> {
+        loop {
+            match self.next_char() {
+                Some('"') if quote == '"' => return Ok(Token::String),
+                Some('\'') if quote == '\'' => return Ok(Token::Char),
+                // The escaped character can't end the string.
+                Some('\\') => {
+                    self.next_char();
+                }
+                Some(_) => {}
+                None if quote == '"' => return Err(ScanError::UnterminatedString),
+                None => return Err(ScanError::UnterminatedChar),
+            }
+        }
+    }
+
+    // Scans the rest of the input, separating lexical errors from tokens.
+    pub fn tokens(self) -> (Vec<Spanned<Token>>, Vec<Spanned<ScanError>>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for Spanned { value, span } in self {
+            match value {
+                Ok(value) => tokens.push(Spanned { value, span }),
+                Err(value) => errors.push(Spanned { value, span }),
+            }
+        }
+        (tokens, errors)
+    }
+
+    // Scans the rest of the input without building spans, e.g. to benchmark
+    // the lexer on its own.
+    pub fn count_tokens(&self) -> usize {
+        let mut scanner = self.clone();
+        let mut count = 0;
+        while scanner.scan_token().is_some() {
+            count += 1;
         }
-        Token::String
+        count
+    }
+
+    // Skips whitespace and scans one token, returning it with its start.
+    fn scan_token(&mut self) -> Option<(Result<Token, ScanError>, usize)> {
+        self.consume_while(|ch| ch.is_ascii_whitespace());
+        let start = self.current_index();
+        let ch = self.next_char()?;
+        let token = match ch {
+            '/' => match self.peek() {
+                '/' => self.comment(),
+                _ => Token::Slash,
+            },
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '.' => Token::Dot,
+            '-' => Token::Minus,
+            '+' => Token::Plus,
+            ';' => Token::Semicolon,
+            '*' => self.if_peek('*', Token::StarStar, Token::Star),
+            '&' => Token::Ampersand,
+            '|' => Token::Pipe,
+            '^' => Token::Caret,
+            '!' => self.if_peek('=', Token::BangEqual, Token::Bang),
+            '=' => self.if_peek('=', Token::EqualEqual, Token::Equal),
+            '<' => match self.peek() {
+                '<' => self.if_peek('<', Token::LessLess, Token::Less),
+                _ => self.if_peek('=', Token::LessEqual, Token::Less),
+            },
+            '>' => match self.peek() {
+                '>' => self.if_peek('>', Token::GreaterGreater, Token::Greater),
+                _ => self.if_peek('=', Token::GreaterEqual, Token::Greater),
+            },
+            '"' | '\'' => return Some((self.quoted(ch), start)),
+            ch if ch.is_ascii_digit() => self.number(),
+            ch if is_alphabetic(ch) => self.identifier_or_keyword(start),
+            ch => return Some((Err(ScanError::UnexpectedCharacter(ch)), start)),
+        };
+        Some((Ok(token), start))
     }
 
     fn identifier_or_keyword(&mut self, start: usize) -> Token {
         self.consume_while(is_alphanumeric);
-        match &self.input[start..self.current_index()] {
-            "and" => Token::And,
-            "class" => Token::Class,
-            "else" => Token::Else,
-            "false" => Token::False,
-            "for" => Token::For,
-            "fun" => Token::Fun,
-            "if" => Token::If,
-            "nil" => Token::Nil,
-            "or" => Token::Or,
-            "print" => Token::Print,
-            "return" => Token::Return,
-            "super" => Token::Super,
-            "this" => Token::This,
-            "true" => Token::True,
-            "var" => Token::Var,
-            "while" => Token::While,
-            _ => Token::Identifier,
-        }
+        keyword(&self.input[start..self.current_index()])
+            .filter(|token| !self.disabled_keywords.contains(token))
+            .unwrap_or(Token::Identifier)
     }
 }
 
+// Sorted, so that lookup can binary search.
+const KEYWORDS: [(&str, Token); 18] = [
+    ("and", Token::And),
+    ("break", Token::Break),
+    ("class", Token::Class),
+    ("continue", Token::Continue),
+    ("else", Token::Else),
+    ("false", Token::False),
+    ("for", Token::For),
+    ("fun", Token::Fun),
+    ("if", Token::If),
+    ("nil", Token::Nil),
+    ("or", Token::Or),
+    ("print", Token::Print),
+    ("return", Token::Return),
+    ("super", Token::Super),
+    ("this", Token::This),
+    ("true", Token::True),
+    ("var", Token::Var),
+    ("while", Token::While),
+];
+
+fn keyword(lexeme: &str) -> Option<Token> {
+    KEYWORDS
+        .binary_search_by(|(keyword, _)| (*keyword).cmp(lexeme))
+        .ok()
+        .map(|index| KEYWORDS[index].1)
+}
+
 impl<'a> Iterator for Scanner<'a> {
-    type Item = Spanned<Token>;
+    type Item = Spanned<Result<Token, ScanError>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.consume_while(|ch| ch.is_ascii_whitespace());
-        let start = self.current_index();
-        self.next_char().map(|ch| {
-            let token = match ch {
-                '/' => match self.peek() {
-                    '/' => self.comment(),
-                    _ => Token::Slash,
-                },
-                '(' => Token::LeftParen,
-                ')' => Token::RightParen,
-                '{' => Token::LeftBrace,
-                '}' => Token::RightBrace,
-                ',' => Token::Comma,
-                '.' => Token::Dot,
-                '-' => Token::Minus,
-                '+' => Token::Plus,
-                ';' => Token::Semicolon,
-                '*' => Token::Star,
-                '!' => self.if_peek('=', Token::BangEqual, Token::Bang),
-                '=' => self.if_peek('=', Token::EqualEqual, Token::Equal),
-                '<' => self.if_peek('=', Token::LessEqual, Token::Less),
-                '>' => self.if_peek('=', Token::GreaterEqual, Token::Greater),
-                '"' => self.string(),
-                ch if ch.is_ascii_digit() => self.number(),
-                ch if is_alphabetic(ch) => self.identifier_or_keyword(start),
-                _ => Token::Unknown,
-            };
-            let span = Span {
-                start: start as i32,
-                end: self.current_index() as i32,
-            };
-            Spanned { value: token, span }
-        })
+        let (token, start) = self.scan_token()?;
+        let span = Span {
+            start: start as i32,
+            end: self.current_index() as i32,
+        };
+        Some(Spanned { value: token, span })
     }
 }
 
+// Identifiers may use any Unicode letters; keywords are all ASCII.
 fn is_alphabetic(ch: char) -> bool {
-    ch.is_ascii_alphabetic() || ch == '_'
+    ch.is_alphabetic() || ch == '_'
 }
 
 fn is_alphanumeric(ch: char) -> bool {
-    ch.is_ascii_alphanumeric() || ch == '_'
+    ch.is_alphanumeric() || ch == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<(Token, &str)> {
+        Scanner::new(input)
+            .map(|token| (token.value.unwrap(), token.lexeme(input)))
+            .collect()
+    }
+
+    fn scan(input: &str) -> Vec<(Result<Token, ScanError>, &str)> {
+        Scanner::new(input)
+            .map(|token| (token.value, token.lexeme(input)))
+            .collect()
+    }
+
+    #[test]
+    fn count_tokens() {
+        let source =
+            "fun f(a, b) {\n  // sum\n  return a + b * 2.5;\n}\nprint f(1, \"x\") >= [1][0];";
+        let scanner = Scanner::new(source);
+        assert_eq!(scanner.count_tokens(), Scanner::new(source).count());
+        assert_eq!(scanner.count_tokens(), 32);
+        assert_eq!(Scanner::new("  ").count_tokens(), 0);
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        assert_eq!(
+            tokens("var café = λ2 + Ωmega_1;"),
+            [
+                (Token::Var, "var"),
+                (Token::Identifier, "café"),
+                (Token::Equal, "="),
+                (Token::Identifier, "λ2"),
+                (Token::Plus, "+"),
+                (Token::Identifier, "Ωmega_1"),
+                (Token::Semicolon, ";"),
+            ]
+        );
+        assert_eq!(tokens("ïf"), [(Token::Identifier, "ïf")]);
+        assert_eq!(scan("→"), [(Err(ScanError::UnexpectedCharacter('→')), "→")]);
+    }
+
+    #[test]
+    fn disabled_keywords() {
+        let source = "and or andy";
+        let tokens = Scanner::without_keywords(source, &[Token::And])
+            .map(|token| token.value.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, [Token::Identifier, Token::Or, Token::Identifier]);
+    }
+
+    #[test]
+    fn token_names() {
+        for (token, name, display) in [
+            (Token::LeftParen, "(", "'('"),
+            (Token::Semicolon, ";", "';'"),
+            (Token::Plus, "+", "'+'"),
+            (Token::BangEqual, "!=", "'!='"),
+            (Token::GreaterGreater, ">>", "'>>'"),
+            (Token::While, "keyword 'while'", "keyword 'while'"),
+            (Token::Nil, "keyword 'nil'", "keyword 'nil'"),
+            (Token::Identifier, "identifier", "identifier"),
+            (Token::String, "string", "string"),
+            (Token::Number, "number", "number"),
+        ] {
+            assert_eq!(token.name(), name);
+            assert_eq!(token.to_string(), display);
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        assert!(KEYWORDS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for (lexeme, token) in KEYWORDS {
+            assert_eq!(tokens(lexeme), [(token, lexeme)]);
+            assert_eq!(token.to_string(), format!("keyword '{}'", lexeme));
+        }
+        for near_miss in ["classy", "an", "_if", "While", "nil2", "fors"] {
+            assert_eq!(tokens(near_miss), [(Token::Identifier, near_miss)]);
+        }
+    }
+
+    #[test]
+    fn fraction_needs_a_digit_after_the_dot() {
+        assert_eq!(tokens("1.5"), [(Token::Number, "1.5")]);
+        assert_eq!(tokens("1."), [(Token::Number, "1"), (Token::Dot, ".")]);
+        assert_eq!(
+            tokens("1.2.3"),
+            [
+                (Token::Number, "1.2"),
+                (Token::Dot, "."),
+                (Token::Number, "3")
+            ]
+        );
+        assert_eq!(tokens(".5"), [(Token::Dot, "."), (Token::Number, "5")]);
+    }
+
+    #[test]
+    fn two_character_tokens_at_end_of_input() {
+        assert_eq!(tokens("<"), [(Token::Less, "<")]);
+        assert_eq!(tokens("<<"), [(Token::LessLess, "<<")]);
+        assert_eq!(tokens(">="), [(Token::GreaterEqual, ">=")]);
+        assert_eq!(tokens("/"), [(Token::Slash, "/")]);
+        assert_eq!(tokens("**"), [(Token::StarStar, "**")]);
+        assert_eq!(tokens("***"), [(Token::StarStar, "**"), (Token::Star, "*")]);
+        assert_eq!(tokens("//"), [(Token::Comment, "//")]);
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let unterminated = Err(ScanError::UnterminatedString);
+        assert_eq!(
+            scan("print \"no end"),
+            [(Ok(Token::Print), "print"), (unterminated, "\"no end")]
+        );
+        assert_eq!(scan("\""), [(unterminated, "\"")]);
+        assert_eq!(tokens("\"done\""), [(Token::String, "\"done\"")]);
+        assert_eq!(tokens("\"a\\\"b\""), [(Token::String, "\"a\\\"b\"")]);
+        assert_eq!(scan("\"a\\\""), [(unterminated, "\"a\\\"")]);
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(tokens("'a'"), [(Token::Char, "'a'")]);
+        assert_eq!(tokens("'\\''"), [(Token::Char, "'\\''")]);
+        assert_eq!(tokens("'\"'"), [(Token::Char, "'\"'")]);
+        assert_eq!(tokens("\"'\""), [(Token::String, "\"'\"")]);
+        assert_eq!(tokens("''"), [(Token::Char, "''")]);
+        assert_eq!(scan("'a"), [(Err(ScanError::UnterminatedChar), "'a")]);
+    }
+
+    #[test]
+    fn tokens_and_errors() {
+        let input = "print 1 @ \"a\"; // done";
+        let (tokens, errors) = Scanner::new(input).tokens();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| (token.value, token.lexeme(input)))
+                .collect::<Vec<_>>(),
+            [
+                (Token::Print, "print"),
+                (Token::Number, "1"),
+                (Token::String, "\"a\""),
+                (Token::Semicolon, ";"),
+                (Token::Comment, "// done"),
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].value, ScanError::UnexpectedCharacter('@'));
+        assert_eq!(errors[0].lexeme(input), "@");
+        assert_eq!(Scanner::new("").tokens().0.len(), 0);
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        assert_eq!(
+            scan("1 @ #2"),
+            [
+                (Ok(Token::Number), "1"),
+                (Err(ScanError::UnexpectedCharacter('@')), "@"),
+                (Err(ScanError::UnexpectedCharacter('#')), "#"),
+                (Ok(Token::Number), "2"),
+            ]
+        );
+        assert_eq!(
+            ScanError::UnexpectedCharacter('@').to_string(),
+            "unexpected character '@'"
+        );
+        assert_eq!(
+            ScanError::UnterminatedString.to_string(),
+            "unterminated string"
+        );
+    }
+
+    #[test]
+    fn peek_past_end() {
+        let scanner = Scanner::new("a");
+        assert_eq!(scanner.peek_nth(0), 'a');
+        assert_eq!(scanner.peek_nth(1), '\0');
+        assert_eq!(scanner.peek_nth(2), '\0');
+    }
 }
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 #[derive(Copy, Clone, Debug)]
 pub struct Span {
     pub start: i32,
@@ -9,3 +11,231 @@ pub struct Spanned<T> {
     pub value: T,
     pub span: Span,
 }
+
+// Only the values are compared, so that the same syntax tree parsed from
+// differently formatted source is equal.
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Span {
+    // The smallest span covering both.
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    // The text the span covers. Spans reaching past the input are cut short
+    // and ones that don't fall on character boundaries give "".
+    pub fn lexeme<'a>(&self, input: &'a str) -> &'a str {
+        let start = (self.start.max(0) as usize).min(input.len());
+        let end = (self.end.max(0) as usize).clamp(start, input.len());
+        input.get(start..end).unwrap_or_default()
+    }
+}
+
+impl<T> Spanned<T> {
+    pub fn lexeme<'a>(&self, input: &'a str) -> &'a str {
+        self.span.lexeme(input)
+    }
+}
+
+pub struct LineMap {
+    starts: Vec<usize>,
+    // Where each line's content ends, before its `\n`, `\r\n` or lone `\r`.
+    ends: Vec<usize>,
+}
+
+impl LineMap {
+    pub fn new(input: &str) -> Self {
+        let mut starts = vec![0];
+        let mut ends = Vec::new();
+        for (index, ch) in input.char_indices() {
+            // A `\r` followed by `\n` is ended along with the `\n`.
+            let end = match ch {
+                '\n' if input[..index].ends_with('\r') => index - 1,
+                '\n' => index,
+                '\r' if !input[index + 1..].starts_with('\n') => index,
+                _ => continue,
+            };
+            ends.push(end);
+            starts.push(index + 1);
+        }
+        ends.push(input.len());
+        Self { starts, ends }
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    pub fn line_col(&self, input: &str, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        let start = self.starts[line];
+        (line, input[start..offset].chars().count())
+    }
+
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        self.starts[line]..self.ends[line]
+    }
+}
+
+pub fn underline(input: &str, lines: &LineMap, span: Span) -> String {
+    let start = (span.start.max(0) as usize).min(input.len());
+    let (line, column) = lines.line_col(input, start);
+    let line = lines.line_range(line);
+    let end = (span.end.max(0) as usize).clamp(start, line.end.max(start));
+    let width = input[start..end].chars().count().max(1);
+    format!(
+        "{}\n{}{}\n",
+        &input[line.clone()],
+        " ".repeat(column),
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{Scanner, Token};
+
+    fn render(input: &str, start: i32, end: i32) -> String {
+        underline(input, &LineMap::new(input), Span { start, end })
+    }
+
+    #[test]
+    fn merge_spans() {
+        let merged = |a: (i32, i32), b: (i32, i32)| {
+            let span = Span {
+                start: a.0,
+                end: a.1,
+            }
+            .merge(Span {
+                start: b.0,
+                end: b.1,
+            });
+            (span.start, span.end)
+        };
+        assert_eq!(merged((2, 6), (4, 9)), (2, 9));
+        assert_eq!(merged((4, 9), (2, 6)), (2, 9));
+        assert_eq!(merged((0, 1), (5, 7)), (0, 7));
+        assert_eq!(merged((5, 7), (0, 1)), (0, 7));
+        assert_eq!(merged((1, 8), (3, 4)), (1, 8));
+    }
+
+    #[test]
+    fn lexemes() {
+        let input = "print café";
+        let tokens = Scanner::new(input).collect::<Vec<_>>();
+        assert_eq!(tokens[0].lexeme(input), "print");
+        assert_eq!(tokens[1].lexeme(input), "café");
+        assert_eq!(tokens[1].span.end as usize, input.len());
+        assert_eq!(Span { start: 6, end: 40 }.lexeme(input), "café");
+        assert_eq!(Span { start: 9, end: 10 }.lexeme(input), "");
+        assert_eq!(Span { start: -1, end: 2 }.lexeme(input), "pr");
+        // Borrowing leaves a non-`Copy` value usable, and the lexeme outlives it.
+        let owned = Spanned {
+            value: String::from("word"),
+            span: Span { start: 0, end: 5 },
+        };
+        let lexeme = owned.lexeme(input);
+        assert_eq!(owned.value, "word");
+        drop(owned);
+        assert_eq!(lexeme, "print");
+    }
+
+    #[test]
+    fn underline_single_line() {
+        assert_eq!(render("1 + true", 4, 8), "1 + true\n    ^^^^\n");
+    }
+
+    #[test]
+    fn underline_later_line() {
+        let input = "print 1;\nprint (2;\nprint 3;";
+        assert_eq!(render(input, 15, 17), "print (2;\n      ^^\n");
+    }
+
+    #[test]
+    fn underline_multiline_span() {
+        let input = "(1 +\n2";
+        assert_eq!(render(input, 0, 6), "(1 +\n^^^^\n");
+    }
+
+    #[test]
+    fn underline_end_of_input() {
+        assert_eq!(render("1 +", 3, 3), "1 +\n   ^\n");
+        assert_eq!(render("", 0, 0), "\n^\n");
+    }
+
+    #[test]
+    fn underline_multi_byte() {
+        let input = "\"héllo\" + 1";
+        assert_eq!(render(input, 0, 8), "\"héllo\" + 1\n^^^^^^^\n");
+        assert_eq!(render(input, 11, 12), "\"héllo\" + 1\n          ^\n");
+    }
+
+    #[test]
+    fn line_col_counts_characters() {
+        let input = "\"naïve\";\n\"日本\" + x;";
+        let lines = LineMap::new(input);
+        assert_eq!(lines.line_col(input, 0), (0, 0));
+        assert_eq!(lines.line_col(input, 9), (0, 8));
+        let plus = input.find('+').unwrap();
+        assert_eq!(lines.line_col(input, plus), (1, 5));
+    }
+
+    #[test]
+    fn caret_after_non_ascii_prefix() {
+        let input = "\"café 🎉\" + true;";
+        let token = Scanner::new(input)
+            .find(|token| token.value == Ok(Token::True))
+            .unwrap();
+        assert_eq!(
+            underline(input, &LineMap::new(input), token.span),
+            "\"café 🎉\" + true;\n           ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let input = "var a = 1;\r\nprint a +\ntrue;\r\n";
+        let lines = LineMap::new(input);
+        assert_eq!(&input[lines.line_range(0)], "var a = 1;");
+        assert_eq!(&input[lines.line_range(1)], "print a +");
+        assert_eq!(&input[lines.line_range(2)], "true;");
+        assert_eq!(&input[lines.line_range(3)], "");
+        let plus = input.find('+').unwrap();
+        assert_eq!(lines.line_col(input, plus), (1, 8));
+        assert_eq!(render(input, 12, 21), "print a +\n^^^^^^^^^\n");
+        let true_start = input.find("true").unwrap() as i32;
+        assert_eq!(render(input, true_start, true_start + 4), "true;\n^^^^\n");
+    }
+
+    #[test]
+    fn lone_carriage_returns() {
+        let input = "var a = 1;\rprint a +\r\rtrue; // done\rprint 2;";
+        let lines = LineMap::new(input);
+        assert_eq!(&input[lines.line_range(0)], "var a = 1;");
+        assert_eq!(&input[lines.line_range(1)], "print a +");
+        assert_eq!(&input[lines.line_range(2)], "");
+        assert_eq!(&input[lines.line_range(3)], "true; // done");
+        assert_eq!(&input[lines.line_range(4)], "print 2;");
+        // The comment ends with its line, as it would before a `\n`.
+        let tokens = Scanner::new(input).collect::<Vec<_>>();
+        let comment = tokens
+            .iter()
+            .find(|token| token.value == Ok(Token::Comment))
+            .unwrap();
+        assert_eq!(comment.lexeme(input), "// done");
+        let last = tokens[tokens.len() - 3];
+        assert_eq!(last.value, Ok(Token::Print));
+        assert_eq!(lines.line_col(input, last.span.start as usize), (4, 0));
+    }
+}
@@ -1,35 +1,351 @@
-use std::fmt::Debug;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Debug, Display},
+    hash::{Hash, Hasher},
+    ops::{Add, Mul},
+    rc::Rc,
+};
+
+use crate::{ast::Function, environment::Environment, interpreter::RuntimeError};
 
 pub type Number = f64;
 
-#[derive(Clone, PartialEq)]
+type MapEntries = RefCell<HashMap<Key, Value>>;
+
+// The longest string that `*` will build by repetition.
+const MAX_REPEAT_LEN: usize = 1 << 28;
+
+#[derive(Clone)]
 pub enum Value {
     String(String),
     Number(Number),
     Boolean(bool),
+    NativeFn(NativeFunction),
+    Function(Rc<UserFunction>),
+    List(Vec<Value>),
+    Map(Rc<MapEntries>),
     Nil,
 }
 
+#[derive(Copy, Clone)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&[Value]) -> Result<Value, RuntimeError>,
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+pub struct UserFunction {
+    pub declaration: Rc<Function>,
+    pub closure: Rc<RefCell<Environment>>,
+}
+
+impl PartialEq for UserFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Lox equality: numbers follow IEEE 754, so `NaN` is unequal to itself and
+// `0.0 == -0.0`. Values of different types are never equal, and `nil` is
+// equal only to `nil`.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::NativeFn(a), Value::NativeFn(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            // Maps are shared and mutable, and may contain themselves.
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
 impl Debug for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    // `printing` holds the maps enclosing this value, so that a map which
+    // contains itself is printed as `{...}` rather than forever.
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        printing: &mut Vec<*const MapEntries>,
+    ) -> fmt::Result {
         match self {
             Self::String(string) => write!(f, "{}", string),
-            Self::Number(number) => write!(f, "{}", number),
+            Self::Number(number) => write!(f, "{}", format_number(*number, f.precision())),
             Self::Boolean(boolean) => write!(f, "{}", boolean),
+            Self::NativeFn(native) => write!(f, "<native fn {}>", native.name),
+            Self::Function(function) => write!(f, "<fn {}>", function.declaration.name),
+            Self::List(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write(f, printing)?;
+                }
+                write!(f, "]")
+            }
+            Self::Map(entries) => {
+                if printing.contains(&Rc::as_ptr(entries)) {
+                    return write!(f, "{{...}}");
+                }
+                printing.push(Rc::as_ptr(entries));
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.value().write(f, printing)?;
+                    write!(f, ": ")?;
+                    value.write(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "}}")
+            }
             Self::Nil => write!(f, "nil"),
         }
     }
 }
 
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+// Without a precision this is the shortest representation that reads back as
+// the same number. With one, the number is rounded to that many significant
+// digits, trailing zeros are dropped, and exponents outside -4..precision
+// switch to scientific notation, the same as C's `%g`. Infinities and NaN are
+// `inf`, `-inf` and `nan` either way.
+pub fn format_number(number: Number, precision: Option<usize>) -> String {
+    if number.is_nan() {
+        return "nan".to_string();
+    }
+    let precision = match precision {
+        Some(precision) if number.is_finite() && number != 0.0 => precision.max(1),
+        _ => return number.to_string(),
+    };
+    let scientific = format!("{:.*e}", precision - 1, number);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        format!("{}e{}", trim_zeros(mantissa), exponent)
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim_zeros(&format!("{:.*}", decimals, number)).to_string()
+    }
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
 impl Value {
     pub fn ty(&self) -> Type {
         match self {
             Value::String(_) => Type::String,
             Value::Number(_) => Type::Number,
             Value::Boolean(_) => Type::Boolean,
+            Value::NativeFn(_) | Value::Function(_) => Type::Function,
+            Value::List(_) => Type::List,
+            Value::Map(_) => Type::Map,
             Value::Nil => Type::Nil,
         }
     }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Boolean(false) | Value::Nil)
+    }
+
+    // A 64-bit FNV-1a hash that, unlike `std::hash::Hash`, is identical across
+    // runs, platforms and compiler versions. Functions have no stable identity.
+    pub fn hash_key(&self) -> Option<u64> {
+        let mut hasher = StableHasher::default();
+        match self {
+            Value::String(string) => {
+                hasher.write(&[0]);
+                hasher.write(string.as_bytes());
+            }
+            Value::Number(number) => {
+                let number = if *number == 0.0 {
+                    0.0
+                } else if number.is_nan() {
+                    Number::NAN
+                } else {
+                    *number
+                };
+                hasher.write(&[1]);
+                hasher.write(&number.to_bits().to_le_bytes());
+            }
+            Value::Boolean(boolean) => hasher.write(&[2, *boolean as u8]),
+            Value::Nil => hasher.write(&[3]),
+            Value::List(values) => {
+                hasher.write(&[4]);
+                hasher.write(&(values.len() as u64).to_le_bytes());
+                for value in values {
+                    hasher.write(&value.hash_key()?.to_le_bytes());
+                }
+            }
+            Value::NativeFn(_) | Value::Function(_) | Value::Map(_) => return None,
+        }
+        Some(hasher.0)
+    }
+}
+
+// Consistent with `PartialEq`: `0.0` and `-0.0` hash alike, functions hash
+// by identity, and maps, which are never keys, only by their type.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::String(string) => string.hash(state),
+            Value::Number(number) => {
+                let number = if *number == 0.0 { 0.0 } else { *number };
+                number.to_bits().hash(state);
+            }
+            Value::Boolean(boolean) => boolean.hash(state),
+            Value::NativeFn(native) => native.name.hash(state),
+            Value::Function(function) => Rc::as_ptr(function).hash(state),
+            Value::List(values) => values.hash(state),
+            Value::Map(_) | Value::Nil => {}
+        }
+    }
+}
+
+// A value that can be used as a map key. `NaN` is never equal to itself, so
+// keys containing it are rejected, which makes equality reflexive. Maps are
+// mutable and can't be keys either.
+#[derive(Clone, PartialEq, Hash, Debug)]
+pub struct Key(Value);
+
+impl Eq for Key {}
+
+impl Key {
+    pub fn new(value: Value) -> Result<Self, RuntimeError> {
+        if !is_valid_key(&value) {
+            return Err(RuntimeError::InvalidKey(value));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+
+    pub fn into_value(self) -> Value {
+        self.0
+    }
+}
+
+fn is_valid_key(value: &Value) -> bool {
+    match value {
+        Value::Number(number) => !number.is_nan(),
+        Value::List(values) => values.iter().all(is_valid_key),
+        Value::Map(_) => false,
+        _ => true,
+    }
+}
+
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl Add for Value {
+    type Output = Result<Value, TypeError>;
+
+    fn add(self, other: Value) -> Self::Output {
+        match (self, other) {
+            (Value::String(left), Value::String(right)) => Ok(Value::String(left + &right)),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            (Value::String(_), right) => Err(TypeError {
+                expected: &[Type::String],
+                actual: right,
+            }),
+            (Value::Number(_), right) => Err(TypeError {
+                expected: &[Type::Number],
+                actual: right,
+            }),
+            (left, _) => Err(TypeError {
+                expected: &[Type::Number, Type::String],
+                actual: left,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MulError {
+    Type(TypeError),
+    // A string was repeated a fractional, negative or excessive number of times.
+    RepeatCount(Number),
+}
+
+impl From<TypeError> for MulError {
+    fn from(error: TypeError) -> Self {
+        Self::Type(error)
+    }
+}
+
+impl Mul for Value {
+    type Output = Result<Value, MulError>;
+
+    fn mul(self, other: Value) -> Self::Output {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
+            (Value::String(left), Value::Number(right)) => {
+                let count = right.as_index().map_err(|_| MulError::RepeatCount(right))?;
+                match left.len().checked_mul(count) {
+                    Some(len) if len <= MAX_REPEAT_LEN => Ok(Value::String(left.repeat(count))),
+                    _ => Err(MulError::RepeatCount(right)),
+                }
+            }
+            (Value::String(_) | Value::Number(_), right) => Err(TypeError {
+                expected: &[Type::Number],
+                actual: right,
+            }
+            .into()),
+            (left, _) => Err(TypeError {
+                expected: &[Type::Number, Type::String],
+                actual: left,
+            }
+            .into()),
+        }
+    }
 }
 
 pub trait Variant: Sized + 'static {
@@ -53,6 +369,22 @@ impl Variant for String {
     }
 }
 
+// `Number` is a plain `f64`, so its helpers live on a trait.
+pub(crate) trait AsIndex {
+    fn as_index(&self) -> Result<usize, RuntimeError>;
+}
+
+impl AsIndex for Number {
+    // Only finite, non-negative whole numbers can index.
+    fn as_index(&self) -> Result<usize, RuntimeError> {
+        if self.is_finite() && *self >= 0.0 && self.fract() == 0.0 {
+            Ok(*self as usize)
+        } else {
+            Err(RuntimeError::InvalidIndex(*self))
+        }
+    }
+}
+
 impl Variant for Number {
     fn into_value(self) -> Value {
         Value::Number(self)
@@ -90,11 +422,194 @@ pub enum Type {
     String,
     Number,
     Boolean,
+    Function,
+    List,
+    Map,
     Nil,
 }
 
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
 #[derive(Debug)]
 pub struct TypeError {
     pub expected: &'static [Type],
     pub actual: Value,
 }
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected ")?;
+        for (index, ty) in self.expected.iter().enumerate() {
+            if index > 0 {
+                write!(f, " or ")?;
+            }
+            write!(f, "{}", ty)?;
+        }
+        write!(f, " but got {}", self.actual.ty())
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_as_index() {
+        assert_eq!(2.0.as_index().unwrap(), 2);
+        assert_eq!((-0.0).as_index().unwrap(), 0);
+        for number in [2.5, -1.0, Number::NAN, Number::INFINITY] {
+            assert!(
+                matches!(number.as_index(), Err(RuntimeError::InvalidIndex(n)) if n.to_bits() == number.to_bits()),
+                "{}",
+                number
+            );
+        }
+    }
+
+    #[test]
+    fn string_repetition() {
+        let repeat = |count| Value::String(String::from("ab")) * Value::Number(count);
+        assert_eq!(repeat(3.0).unwrap(), Value::String(String::from("ababab")));
+        assert_eq!(repeat(0.0).unwrap(), Value::String(String::new()));
+        for count in [-2.0, 2.5, Number::NAN, Number::INFINITY, 1e11, 1e20] {
+            assert!(
+                matches!(repeat(count), Err(MulError::RepeatCount(n)) if n.to_bits() == count.to_bits()),
+                "{}",
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn non_finite_numbers() {
+        for (number, expected) in [
+            (1e308 * 10.0, "inf"),
+            (-1e308 * 10.0, "-inf"),
+            (Number::NAN, "nan"),
+            (-Number::NAN, "nan"),
+        ] {
+            assert_eq!(Value::Number(number).to_string(), expected);
+            assert_eq!(format!("{:.3}", Value::Number(number)), expected);
+        }
+        let list = Value::List(vec![
+            Value::Number(Number::NEG_INFINITY),
+            Value::Number(Number::NAN),
+        ]);
+        assert_eq!(list.to_string(), "[-inf, nan]");
+    }
+
+    #[test]
+    fn number_precision() {
+        let third = 1.0 / 3.0;
+        assert_eq!(format_number(third, None), "0.3333333333333333");
+        assert_eq!(format_number(third, Some(3)), "0.333");
+        for (number, precision, expected) in [
+            (2.0, None, "2"),
+            (2.0, Some(3), "2"),
+            (1234.5, Some(2), "1.2e3"),
+            (1234.5, Some(4), "1234"),
+            (0.000012345, Some(3), "1.23e-5"),
+            (0.00012345, Some(3), "0.000123"),
+            (1e300, Some(3), "1e300"),
+            (-2.6, Some(1), "-3"),
+            (0.5, Some(0), "0.5"),
+            (Number::INFINITY, Some(3), "inf"),
+            (Number::NAN, Some(3), "nan"),
+        ] {
+            assert_eq!(format_number(number, precision), expected, "{}", number);
+        }
+        let list = Value::List(vec![Value::Number(third), Value::String("x".into())]);
+        assert_eq!(format!("{:.2}", list), "[0.33, x]");
+    }
+
+    #[test]
+    fn map_keys() {
+        let mut map = std::collections::HashMap::new();
+        let keys = [
+            Value::Number(0.0),
+            Value::Number(1.5),
+            Value::String(String::from("key")),
+            Value::Boolean(true),
+            Value::Nil,
+        ];
+        for (index, key) in keys.iter().enumerate() {
+            map.insert(Key::new(key.clone()).unwrap(), index);
+        }
+        for (index, key) in keys.iter().enumerate() {
+            assert_eq!(map[&Key::new(key.clone()).unwrap()], index);
+        }
+        assert_eq!(map[&Key::new(Value::Number(-0.0)).unwrap()], 0);
+        assert!(!map.contains_key(&Key::new(Value::String(String::from("1.5"))).unwrap()));
+    }
+
+    #[test]
+    fn nan_keys_are_rejected() {
+        assert!(matches!(
+            Key::new(Value::Number(Number::NAN)),
+            Err(RuntimeError::InvalidKey(Value::Number(_)))
+        ));
+        assert!(Key::new(Value::List(vec![Value::Nil, Value::Number(Number::NAN)])).is_err());
+    }
+
+    #[test]
+    fn hash_key_is_stable() {
+        let values = [
+            Value::String(String::from("key")),
+            Value::Number(1.5),
+            Value::Boolean(true),
+            Value::Nil,
+        ];
+        for value in &values {
+            assert_eq!(value.hash_key(), value.clone().hash_key());
+        }
+        assert_eq!(Value::Nil.hash_key(), Some(0xaf63be4c8601b992));
+    }
+
+    #[test]
+    fn hash_key_distinguishes_values() {
+        let keys = [
+            Value::String(String::from("1")),
+            Value::String(String::from("")),
+            Value::Number(1.0),
+            Value::Number(2.0),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Nil,
+        ]
+        .iter()
+        .map(|value| value.hash_key().unwrap())
+        .collect::<std::collections::HashSet<_>>();
+        assert_eq!(keys.len(), 7);
+    }
+
+    #[test]
+    fn hash_key_normalizes_zero() {
+        assert_eq!(
+            Value::Number(0.0).hash_key(),
+            Value::Number(-0.0).hash_key()
+        );
+    }
+
+    #[test]
+    fn type_error_message() {
+        let error = TypeError {
+            expected: &[Type::Number, Type::String],
+            actual: Value::Boolean(true),
+        };
+        assert_eq!(
+            error.to_string(),
+            "expected Number or String but got Boolean"
+        );
+        let error = TypeError {
+            expected: &[Type::Number],
+            actual: Value::Nil,
+        };
+        assert_eq!(error.to_string(), "expected Number but got Nil");
+    }
+}
@@ -1,4 +1,11 @@
-pub type Value = f64;
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    ops::{Add, Mul},
+    rc::Rc,
+};
+
+use crate::value::{MulError, Number, TypeError, Value, Variant};
 
 #[derive(Default)]
 pub struct Chunk {
@@ -14,51 +21,180 @@ impl Chunk {
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
+        if let Some(index) = self
+            .constants
+            .iter()
+            .position(|constant| same_constant(constant, &value))
+        {
+            return index;
+        }
         let index = self.constants.len();
         self.constants.push(value);
         index
     }
+
+    // Overwrites already written code, keeping its lines.
+    pub fn patch(&mut self, offset: usize, bytes: &[u8]) {
+        self.code[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    pub fn line_at(&self, offset: usize) -> u32 {
+        self.lines[offset]
+    }
+}
+
+// NaN never matches itself, and 0.0 and -0.0 are distinct constants even
+// though they compare equal.
+fn same_constant(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => {
+            !left.is_nan() && left.to_bits() == right.to_bits()
+        }
+        (left, right) => left == right,
+    }
 }
 
 pub fn disassemble(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
+    let mut out = String::new();
+    write_chunk(&mut out, chunk, name, None).expect("formatting into a String cannot fail");
+    print!("{}", out);
+}
+
+pub fn annotated_disassembly(chunk: &Chunk, name: &str, source: &str) -> String {
+    let mut out = String::new();
+    write_chunk(&mut out, chunk, name, Some(source)).expect("formatting into a String cannot fail");
+    out
+}
+
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
+    let mut out = String::new();
+    let next =
+        write_instruction(&mut out, chunk, offset).expect("formatting into a String cannot fail");
+    print!("{}", out);
+    next
+}
+
+fn write_chunk<W: Write>(
+    out: &mut W,
+    chunk: &Chunk,
+    name: &str,
+    source: Option<&str>,
+) -> fmt::Result {
+    writeln!(out, "== {} ==", name)?;
+    let lines = source.map(|source| source.lines().collect::<Vec<_>>());
     let mut offset = 0;
     while offset < chunk.code.len() {
-        print!("{:04} ", offset);
-        if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-            print!("   | ");
-        } else {
-            print!("{:4} ", chunk.lines[offset]);
-        }
-        let instruction = chunk.code[offset];
-        offset += match instruction {
-            op::RETURN => simple_instruction("OP_RETURN"),
-            op::CONSTANT => constant_instruction("OP_CONSTANT", chunk, offset),
-            op::NEGATE => simple_instruction("OP_NEGATE"),
-            op::ADD => simple_instruction("OP_ADD"),
-            op::SUBTRACT => simple_instruction("OP_SUBTRACT"),
-            op::MULTIPLY => simple_instruction("OP_MULTIPLY"),
-            op::DIVIDE => simple_instruction("OP_DIVIDE"),
-            _ => panic!("Illegal instruction {}", instruction),
+        let line = chunk.lines[offset];
+        if let Some(lines) = &lines {
+            if offset == 0 || line != chunk.lines[offset - 1] {
+                let text = lines.get(line as usize - 1).copied().unwrap_or_default();
+                writeln!(out, "// {}: {}", line, text.trim())?;
+            }
         }
+        offset = write_instruction(out, chunk, offset)?;
+    }
+    Ok(())
+}
+
+fn write_instruction<W: Write>(
+    out: &mut W,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    write!(out, "{:04} ", offset)?;
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        write!(out, "   | ")?;
+    } else {
+        write!(out, "{:4} ", chunk.lines[offset])?;
     }
+    let instruction = chunk.code[offset];
+    let size = match instruction {
+        op::RETURN => simple_instruction(out, "OP_RETURN")?,
+        op::CONSTANT => constant_instruction(out, "OP_CONSTANT", chunk, offset)?,
+        op::NEGATE => simple_instruction(out, "OP_NEGATE")?,
+        op::ADD => simple_instruction(out, "OP_ADD")?,
+        op::SUBTRACT => simple_instruction(out, "OP_SUBTRACT")?,
+        op::MULTIPLY => simple_instruction(out, "OP_MULTIPLY")?,
+        op::DIVIDE => simple_instruction(out, "OP_DIVIDE")?,
+        op::NOT => simple_instruction(out, "OP_NOT")?,
+        op::EQUAL => simple_instruction(out, "OP_EQUAL")?,
+        op::GREATER => simple_instruction(out, "OP_GREATER")?,
+        op::LESS => simple_instruction(out, "OP_LESS")?,
+        op::POP => simple_instruction(out, "OP_POP")?,
+        op::CALL_NATIVE => call_native_instruction(out, "OP_CALL_NATIVE", chunk, offset)?,
+        op::TRUE => simple_instruction(out, "OP_TRUE")?,
+        op::FALSE => simple_instruction(out, "OP_FALSE")?,
+        op::NIL => simple_instruction(out, "OP_NIL")?,
+        op::JUMP => jump_instruction(out, "OP_JUMP", 1, chunk, offset)?,
+        op::JUMP_IF_FALSE => jump_instruction(out, "OP_JUMP_IF_FALSE", 1, chunk, offset)?,
+        op::LOOP => jump_instruction(out, "OP_LOOP", -1, chunk, offset)?,
+        op::DEFINE_GLOBAL => constant_instruction(out, "OP_DEFINE_GLOBAL", chunk, offset)?,
+        op::GET_GLOBAL => constant_instruction(out, "OP_GET_GLOBAL", chunk, offset)?,
+        op::SET_GLOBAL => constant_instruction(out, "OP_SET_GLOBAL", chunk, offset)?,
+        op::GET_LOCAL => byte_instruction(out, "OP_GET_LOCAL", chunk, offset)?,
+        op::SET_LOCAL => byte_instruction(out, "OP_SET_LOCAL", chunk, offset)?,
+        _ => panic!("Illegal instruction {}", instruction),
+    };
+    Ok(offset + size)
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn constant_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
     let constant = chunk.code[offset + 1];
-    print!("{} {:4} '", name, constant);
-    print_value(chunk.constants[constant as usize]);
-    println!("'");
-    2
+    writeln!(
+        out,
+        "{} {:4} '{}'",
+        name, constant, chunk.constants[constant as usize]
+    )?;
+    Ok(2)
 }
 
-fn print_value(value: f64) {
-    print!("{}", value);
+fn byte_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    writeln!(out, "{} {:4}", name, chunk.code[offset + 1])?;
+    Ok(2)
+}
+
+fn jump_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    sign: isize,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let distance = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let target = (offset + 3) as isize + sign * distance as isize;
+    writeln!(out, "{} {:4} -> {}", name, distance, target)?;
+    Ok(3)
 }
 
-fn simple_instruction(name: &str) -> usize {
-    println!("{}", name);
-    1
+fn call_native_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let native = chunk.code[offset + 1];
+    let arg_count = chunk.code[offset + 2];
+    writeln!(out, "{} {:4} ({} args)", name, native, arg_count)?;
+    Ok(3)
+}
+
+fn simple_instruction<W: Write>(out: &mut W, name: &str) -> Result<usize, fmt::Error> {
+    writeln!(out, "{}", name)?;
+    Ok(1)
+}
+
+fn print_value(value: &Value) {
+    print!("{}", value);
 }
 
 pub mod op {
@@ -69,101 +205,367 @@ pub mod op {
     pub const SUBTRACT: u8 = 4;
     pub const MULTIPLY: u8 = 5;
     pub const DIVIDE: u8 = 6;
+    pub const NOT: u8 = 7;
+    pub const EQUAL: u8 = 8;
+    pub const GREATER: u8 = 9;
+    pub const LESS: u8 = 10;
+    pub const POP: u8 = 11;
+    pub const CALL_NATIVE: u8 = 12;
+    pub const TRUE: u8 = 13;
+    pub const FALSE: u8 = 14;
+    pub const NIL: u8 = 15;
+    // Followed by a big-endian distance forward from the next instruction,
+    // or backward for `LOOP`. `JUMP_IF_FALSE` leaves the condition on the
+    // stack.
+    pub const JUMP: u8 = 16;
+    pub const JUMP_IF_FALSE: u8 = 17;
+    pub const LOOP: u8 = 18;
+    // Followed by the constant holding the variable's name.
+    pub const DEFINE_GLOBAL: u8 = 19;
+    pub const GET_GLOBAL: u8 = 20;
+    pub const SET_GLOBAL: u8 = 21;
+    // Followed by the local's stack slot, counted from the frame's base.
+    pub const GET_LOCAL: u8 = 22;
+    pub const SET_LOCAL: u8 = 23;
 }
 
 const STACK_SIZE: usize = 256;
 
-pub struct VirtualMachine {
+pub type Native = Rc<dyn Fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>>;
+
+struct CallFrame {
+    chunk: Rc<Chunk>,
     ip: usize,
-    stack: [Value; STACK_SIZE],
-    stack_top: usize,
+    base: usize,
+}
+
+pub struct VirtualMachine {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    natives: Vec<Native>,
+    globals: HashMap<String, Value>,
+    pub trace: bool,
 }
 
 impl VirtualMachine {
-    pub fn run(&mut self, chunk: &Chunk) -> Result<(), Error> {
-        self.ip = 0;
+    pub fn define_native(&mut self, native: Native) -> u8 {
+        self.natives.push(native);
+        (self.natives.len() - 1) as u8
+    }
+
+    pub fn run(&mut self, chunk: Rc<Chunk>) -> Result<Value, RuntimeError> {
+        self.call(chunk, &[])
+    }
+
+    pub fn call(&mut self, chunk: Rc<Chunk>, args: &[Value]) -> Result<Value, RuntimeError> {
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        self.stack.extend_from_slice(args);
+        self.frames.push(CallFrame { chunk, ip: 0, base });
+        let result = self.execute();
+        self.frames.truncate(depth);
+        self.stack.truncate(base);
+        result
+    }
+
+    // Attaches the line of the instruction being executed. Natives can use it
+    // to report their own errors at the line of the call.
+    pub fn error(&self, error: Error) -> RuntimeError {
+        let frame = self.frame();
+        RuntimeError {
+            error,
+            line: frame.chunk.line_at(frame.ip.saturating_sub(1)),
+        }
+    }
+
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
         loop {
-            let instruction = self.read_byte(chunk);
-            match instruction {
+            if self.trace {
+                self.trace_instruction();
+            }
+            let instruction = self.read_byte();
+            let result = match instruction {
                 op::RETURN => {
-                    println!("{}", self.pop());
-                    return Ok(());
+                    let base = self.frame().base;
+                    if self.stack.len() > base {
+                        return Ok(self.pop());
+                    }
+                    return Ok(Value::Nil);
                 }
                 op::CONSTANT => {
-                    let value = self.read_constant(chunk);
+                    let value = self.read_constant();
                     self.push(value);
+                    Ok(())
                 }
-                op::NEGATE => self.unary(|a| -a),
-                op::ADD => self.binary(|a, b| a + b),
-                op::SUBTRACT => self.binary(|a, b| a - b),
-                op::MULTIPLY => self.binary(|a, b| a * b),
-                op::DIVIDE => self.binary(|a, b| a / b),
-                _ => {}
-            }
+                op::TRUE => {
+                    self.push(Value::Boolean(true));
+                    Ok(())
+                }
+                op::FALSE => {
+                    self.push(Value::Boolean(false));
+                    Ok(())
+                }
+                op::NIL => {
+                    self.push(Value::Nil);
+                    Ok(())
+                }
+                op::JUMP => {
+                    let distance = self.read_short();
+                    self.frame_mut().ip += distance as usize;
+                    Ok(())
+                }
+                op::JUMP_IF_FALSE => {
+                    let distance = self.read_short();
+                    if !self.peek().is_truthy() {
+                        self.frame_mut().ip += distance as usize;
+                    }
+                    Ok(())
+                }
+                op::LOOP => {
+                    let distance = self.read_short();
+                    self.frame_mut().ip -= distance as usize;
+                    Ok(())
+                }
+                op::DEFINE_GLOBAL => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                    Ok(())
+                }
+                op::GET_GLOBAL => {
+                    let name = self.read_name();
+                    match self.globals.get(&name) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.push(value);
+                            Ok(())
+                        }
+                        None => Err(Error::UndefinedVariable(name)),
+                    }
+                }
+                // Assignment is an expression, so the value stays on the stack.
+                op::SET_GLOBAL => {
+                    let name = self.read_name();
+                    let value = self.peek().clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => {
+                            *global = value;
+                            Ok(())
+                        }
+                        None => Err(Error::UndefinedVariable(name)),
+                    }
+                }
+                op::GET_LOCAL => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.push(self.stack[slot].clone());
+                    Ok(())
+                }
+                op::SET_LOCAL => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek().clone();
+                    Ok(())
+                }
+                op::NEGATE => self.unary(|a: Number| -a),
+                op::ADD => self.binary(Value::add),
+                op::SUBTRACT => self.binary(numeric(|a, b| a - b)),
+                op::MULTIPLY => self.binary(Value::mul),
+                op::DIVIDE => self.binary(numeric(|a, b| a / b)),
+                op::NOT => {
+                    let value = self.pop();
+                    self.push(Value::Boolean(!value.is_truthy()));
+                    Ok(())
+                }
+                op::EQUAL => self.binary(|a, b| Ok::<_, TypeError>(Value::Boolean(a == b))),
+                op::GREATER => self.binary(comparison(|a, b| a > b)),
+                op::LESS => self.binary(comparison(|a, b| a < b)),
+                op::POP => {
+                    self.pop();
+                    Ok(())
+                }
+                op::CALL_NATIVE => {
+                    let native = self.read_byte();
+                    let arg_count = self.read_byte() as usize;
+                    let Some(native) = self.natives.get(native as usize).cloned() else {
+                        return Err(self.error(Error::UndefinedNative(native)));
+                    };
+                    let args = self.stack.split_off(self.stack.len() - arg_count);
+                    let value = native(self, &args)?;
+                    self.push(value);
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+            result.map_err(|error| self.error(error))?;
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no call frame")
+    }
+
+    fn trace_instruction(&self) {
+        print!("          ");
+        for value in &self.stack {
+            print!("[ ");
+            print_value(value);
+            print!(" ]");
+        }
+        println!();
+        let frame = self.frame();
+        disassemble_instruction(&frame.chunk, frame.ip);
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let constant = self.read_byte();
+        self.frame().chunk.constants[constant as usize].clone()
+    }
+
+    fn read_name(&mut self) -> String {
+        match self.read_constant() {
+            Value::String(name) => name,
+            constant => panic!("variable name must be a string, got {:?}", constant),
         }
     }
 
-    fn read_constant(&mut self, chunk: &Chunk) -> Value {
-        let constant = self.read_byte(chunk);
-        chunk.constants[constant as usize]
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no call frame")
     }
 
-    fn read_byte(&mut self, chunk: &Chunk) -> u8 {
-        let byte = chunk.code[self.ip];
-        self.ip += 1;
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.chunk.code[frame.ip];
+        frame.ip += 1;
         byte
     }
 
     fn push(&mut self, value: Value) {
-        self.stack[self.stack_top] = value;
-        self.stack_top += 1;
+        self.stack.push(value);
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("stack underflow")
     }
 
     fn pop(&mut self) -> Value {
-        self.stack_top -= 1;
-        self.stack[self.stack_top]
+        self.stack.pop().expect("stack underflow")
     }
 
-    fn binary<F>(&mut self, op: F)
+    fn binary<E, F>(&mut self, op: F) -> Result<(), Error>
     where
-        F: Fn(Value, Value) -> Value,
+        Error: From<E>,
+        F: Fn(Value, Value) -> Result<Value, E>,
     {
-        let left = self.pop();
         let right = self.pop();
-        self.push(op(left, right));
+        let left = self.pop();
+        self.push(op(left, right)?);
+        Ok(())
     }
 
-    fn unary<F>(&mut self, op: F)
+    fn unary<A, B, F>(&mut self, op: F) -> Result<(), Error>
     where
-        F: Fn(Value) -> Value,
+        A: Variant,
+        B: Variant,
+        F: Fn(A) -> B,
     {
-        let arg = self.pop();
-        self.push(op(arg));
+        let arg = A::from_value(self.pop())?;
+        self.push(op(arg).into_value());
+        Ok(())
+    }
+}
+
+fn numeric<F>(op: F) -> impl Fn(Value, Value) -> Result<Value, TypeError>
+where
+    F: Fn(Number, Number) -> Number,
+{
+    move |left, right| {
+        Ok(Value::Number(op(
+            Number::from_value(left)?,
+            Number::from_value(right)?,
+        )))
+    }
+}
+
+fn comparison<F>(op: F) -> impl Fn(Value, Value) -> Result<Value, TypeError>
+where
+    F: Fn(Number, Number) -> bool,
+{
+    move |left, right| {
+        Ok(Value::Boolean(op(
+            Number::from_value(left)?,
+            Number::from_value(right)?,
+        )))
     }
 }
 
 impl Default for VirtualMachine {
     fn default() -> Self {
         Self {
-            ip: Default::default(),
-            stack: [Value::default(); STACK_SIZE],
-            stack_top: Default::default(),
+            frames: Vec::new(),
+            stack: Vec::with_capacity(STACK_SIZE),
+            natives: Vec::new(),
+            globals: HashMap::new(),
+            trace: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    TypeError(TypeError),
+    UndefinedNative(u8),
+    UndefinedVariable(String),
+    RepeatCount(Number),
+}
+
+impl From<TypeError> for Error {
+    fn from(error: TypeError) -> Self {
+        Self::TypeError(error)
+    }
+}
+
+impl From<MulError> for Error {
+    fn from(error: MulError) -> Self {
+        match error {
+            MulError::Type(error) => Self::TypeError(error),
+            MulError::RepeatCount(count) => Self::RepeatCount(count),
         }
     }
 }
 
 #[derive(Debug)]
-pub enum Error {}
+pub struct RuntimeError {
+    pub error: Error,
+    pub line: u32,
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        ast::{BinaryOperator, Expression, LogicalOperator, Node, Statement},
+        compiler::{self, Compiler},
+        parser::Parser,
+        scanner::Scanner,
+        span::{Span, Spanned},
+    };
+    use std::cell::Cell;
+
+    fn node(expression: Expression) -> Box<Node> {
+        Box::new(Spanned {
+            value: expression,
+            span: Span { start: 0, end: 0 },
+        })
+    }
 
     #[test]
     pub fn disassemble_something() {
         let mut chunk = Chunk::default();
-        let c0 = chunk.add_constant(1.2);
-        let c1 = chunk.add_constant(-9.3);
+        let c0 = chunk.add_constant(Value::Number(1.2));
+        let c1 = chunk.add_constant(Value::Number(-9.3));
         chunk.write(op::CONSTANT, 123);
         chunk.write(c0 as u8, 123);
         chunk.write(op::CONSTANT, 123);
@@ -172,6 +574,578 @@ mod tests {
         chunk.write(op::RETURN, 123);
         disassemble(&chunk, "test chunk");
         let mut vm = VirtualMachine::default();
-        println!("{:?}", vm.run(&chunk));
+        println!("{:?}", vm.run(Rc::new(chunk)));
+    }
+
+    #[test]
+    pub fn concatenate_strings() {
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::String(String::from("foo")));
+        let c1 = chunk.add_constant(Value::String(String::from("bar")));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c1 as u8, 1);
+        chunk.write(op::ADD, 1);
+        chunk.write(op::RETURN, 1);
+        let mut vm = VirtualMachine::default();
+        assert_eq!(
+            vm.run(Rc::new(chunk)).unwrap(),
+            Value::String(String::from("foobar"))
+        );
+    }
+
+    #[test]
+    pub fn add_string_to_number() {
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::String(String::from("foo")));
+        let c1 = chunk.add_constant(Value::Number(1.0));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c1 as u8, 1);
+        chunk.write(op::ADD, 1);
+        chunk.write(op::RETURN, 1);
+        let mut vm = VirtualMachine::default();
+        assert!(matches!(
+            vm.run(Rc::new(chunk)),
+            Err(RuntimeError {
+                error: Error::TypeError(_),
+                line: 1
+            })
+        ));
+    }
+
+    #[test]
+    pub fn pop_expression_statements() {
+        let mut compiler = Compiler::default();
+        compiler
+            .expression_statement(&Expression::Binary(
+                BinaryOperator::Add,
+                node(Expression::Literal(Value::Number(1.0))),
+                node(Expression::Literal(Value::Number(2.0))),
+            ))
+            .unwrap();
+        compiler
+            .expression_statement(&Expression::Literal(Value::Boolean(true)))
+            .unwrap();
+        let chunk = compiler.finish();
+        let mut vm = VirtualMachine::default();
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Nil);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    pub fn compile_boolean_and_nil_literals() {
+        for (value, opcode) in [
+            (Value::Boolean(true), op::TRUE),
+            (Value::Boolean(false), op::FALSE),
+            (Value::Nil, op::NIL),
+        ] {
+            let mut compiler = Compiler::default();
+            compiler
+                .expression(&Expression::Literal(value.clone()))
+                .unwrap();
+            let chunk = compiler.finish();
+            assert_eq!(chunk.code, [opcode, op::RETURN]);
+            assert!(chunk.constants.is_empty());
+            let mut vm = VirtualMachine::default();
+            assert_eq!(vm.run(Rc::new(chunk)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    pub fn trace_execution() {
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::Number(3.0));
+        let c1 = chunk.add_constant(Value::Number(4.0));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c1 as u8, 1);
+        chunk.write(op::MULTIPLY, 1);
+        chunk.write(op::NEGATE, 2);
+        chunk.write(op::RETURN, 2);
+        let mut vm = VirtualMachine {
+            trace: true,
+            ..Default::default()
+        };
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Number(-12.0));
+    }
+
+    #[test]
+    pub fn native_calls_back_into_vm() {
+        let mut function = Chunk::default();
+        let c0 = function.add_constant(Value::Number(2.0));
+        function.write(op::CONSTANT, 1);
+        function.write(c0 as u8, 1);
+        function.write(op::MULTIPLY, 1);
+        function.write(op::RETURN, 1);
+        let function = Rc::new(function);
+
+        let mut vm = VirtualMachine::default();
+        let apply = vm.define_native(Rc::new(move |vm, args| vm.call(function.clone(), args)));
+
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::Number(20.0));
+        let c1 = chunk.add_constant(Value::Number(1.0));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::CALL_NATIVE, 1);
+        chunk.write(apply, 1);
+        chunk.write(1, 1);
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c1 as u8, 1);
+        chunk.write(op::ADD, 1);
+        chunk.write(op::RETURN, 1);
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Number(41.0));
+        assert!(vm.frames.is_empty());
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    pub fn runtime_error_line() {
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::Number(1.0));
+        let c1 = chunk.add_constant(Value::Boolean(true));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::NEGATE, 2);
+        chunk.write(op::CONSTANT, 3);
+        chunk.write(c1 as u8, 3);
+        chunk.write(op::SUBTRACT, 4);
+        chunk.write(op::RETURN, 5);
+        let mut vm = VirtualMachine::default();
+        let error = vm.run(Rc::new(chunk)).unwrap_err();
+        assert!(matches!(error.error, Error::TypeError(_)));
+        assert_eq!(error.line, 4);
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    pub fn native_error_line() {
+        let mut vm = VirtualMachine::default();
+        let fail = vm.define_native(Rc::new(|vm, _| Err(vm.error(Error::UndefinedNative(0)))));
+        let mut chunk = Chunk::default();
+        chunk.write(op::CALL_NATIVE, 7);
+        chunk.write(fail, 7);
+        chunk.write(0, 7);
+        chunk.write(op::RETURN, 8);
+        let error = vm.run(Rc::new(chunk)).unwrap_err();
+        assert_eq!(error.line, 7);
+
+        let mut chunk = Chunk::default();
+        chunk.write(op::CALL_NATIVE, 2);
+        chunk.write(fail + 1, 2);
+        chunk.write(0, 2);
+        chunk.write(op::RETURN, 3);
+        assert!(matches!(
+            vm.run(Rc::new(chunk)),
+            Err(RuntimeError {
+                error: Error::UndefinedNative(_),
+                line: 2
+            })
+        ));
+    }
+
+    #[test]
+    pub fn disassemble_with_source() {
+        let source = "1 + 2;\n-3;\n";
+        let mut chunk = Chunk::default();
+        let c0 = chunk.add_constant(Value::Number(1.0));
+        let c1 = chunk.add_constant(Value::Number(2.0));
+        let c2 = chunk.add_constant(Value::Number(3.0));
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c0 as u8, 1);
+        chunk.write(op::CONSTANT, 1);
+        chunk.write(c1 as u8, 1);
+        chunk.write(op::ADD, 1);
+        chunk.write(op::POP, 1);
+        chunk.write(op::CONSTANT, 2);
+        chunk.write(c2 as u8, 2);
+        chunk.write(op::NEGATE, 2);
+        chunk.write(op::POP, 2);
+        chunk.write(op::RETURN, 2);
+        assert_eq!(
+            annotated_disassembly(&chunk, "program", source),
+            "\
+== program ==
+// 1: 1 + 2;
+0000    1 OP_CONSTANT    0 '1'
+0002    | OP_CONSTANT    1 '2'
+0004    | OP_ADD
+0005    | OP_POP
+// 2: -3;
+0006    2 OP_CONSTANT    2 '3'
+0008    | OP_NEGATE
+0009    | OP_POP
+0010    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    pub fn deduplicate_constants() {
+        let mut chunk = Chunk::default();
+        assert_eq!(chunk.add_constant(Value::Number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Value::String(String::from("a"))), 1);
+        assert_eq!(chunk.add_constant(Value::Number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Value::String(String::from("a"))), 1);
+        assert_eq!(chunk.add_constant(Value::Number(f64::NAN)), 2);
+        assert_eq!(chunk.add_constant(Value::Number(f64::NAN)), 3);
+        assert_eq!(chunk.add_constant(Value::Number(0.0)), 4);
+        assert_eq!(chunk.add_constant(Value::Number(-0.0)), 5);
+        assert_eq!(chunk.add_constant(Value::Number(-0.0)), 5);
+        assert_eq!(chunk.constants.len(), 6);
+    }
+
+    #[test]
+    pub fn compile_repeated_constants() {
+        let one = || node(Expression::Literal(Value::Number(1.0)));
+        let expression = Expression::Binary(
+            BinaryOperator::Add,
+            node(Expression::Binary(BinaryOperator::Add, one(), one())),
+            one(),
+        );
+        let mut compiler = Compiler::default();
+        compiler.expression(&expression).unwrap();
+        let chunk = compiler.finish();
+        assert_eq!(chunk.constants, vec![Value::Number(1.0)]);
+        let mut vm = VirtualMachine::default();
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Number(3.0));
+    }
+
+    fn compile(source: &str) -> Chunk {
+        let mut compiler = Compiler::default();
+        compiler.set_line(1);
+        for statement in Parser::new(source, Scanner::new(source)) {
+            match statement.value.unwrap() {
+                Statement::Expression(expression) => compiler.expression(&expression).unwrap(),
+                statement => panic!("expected an expression, got {:?}", statement),
+            }
+        }
+        compiler.finish()
+    }
+
+    #[test]
+    pub fn short_circuit() {
+        // Negating a string fails, so the trap must never run.
+        for (source, expected) in [
+            ("false and -\"trap\"", Value::Boolean(false)),
+            ("nil and -\"trap\"", Value::Nil),
+            ("true or -\"trap\"", Value::Boolean(true)),
+            ("0 or -\"trap\"", Value::Number(0.0)),
+            ("true and 2", Value::Number(2.0)),
+            ("nil or 2", Value::Number(2.0)),
+            ("false or nil", Value::Nil),
+            ("1 and nil or 3", Value::Number(3.0)),
+        ] {
+            let mut vm = VirtualMachine::default();
+            assert_eq!(
+                vm.run(Rc::new(compile(source))).unwrap(),
+                expected,
+                "{}",
+                source
+            );
+            assert!(vm.stack.is_empty(), "{}", source);
+        }
+        let mut vm = VirtualMachine::default();
+        assert!(vm.run(Rc::new(compile("true and -\"trap\""))).is_err());
+    }
+
+    #[test]
+    pub fn patch_forward_jump() {
+        let mut compiler = Compiler::default();
+        let jump = compiler.emit_jump(op::JUMP);
+        compiler
+            .expression(&Expression::Literal(Value::Number(1.0)))
+            .unwrap();
+        compiler.patch_jump(jump).unwrap();
+        compiler
+            .expression(&Expression::Literal(Value::Boolean(true)))
+            .unwrap();
+        let chunk = compiler.finish();
+        // The jump skips the two bytes of the constant.
+        assert_eq!(jump, 1);
+        assert_eq!(chunk.code[..3], [op::JUMP, 0, 2]);
+        let mut vm = VirtualMachine::default();
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Boolean(true));
+    }
+
+    #[test]
+    pub fn jump_too_long() {
+        let nils = (0..40_000)
+            .map(|_| *node(Expression::Literal(Value::Nil)))
+            .collect();
+        let expression = Expression::Logical(
+            LogicalOperator::And,
+            node(Expression::Literal(Value::Boolean(true))),
+            node(Expression::Comma(nils)),
+        );
+        let mut compiler = Compiler::default();
+        assert!(matches!(
+            compiler.expression(&expression),
+            Err(compiler::Error::JumpTooLong)
+        ));
+    }
+
+    #[test]
+    pub fn countdown_loop() {
+        let count = Rc::new(Cell::new(3));
+        let mut vm = VirtualMachine::default();
+        let counter = count.clone();
+        let tick = vm.define_native(Rc::new(move |_, _| {
+            counter.set(counter.get() - 1);
+            Ok(Value::Boolean(counter.get() > 0))
+        }));
+        // while (tick()) {} return 42;
+        let mut chunk = Chunk::default();
+        let answer = chunk.add_constant(Value::Number(42.0));
+        for byte in [
+            op::CALL_NATIVE,
+            tick,
+            0,
+            op::JUMP_IF_FALSE,
+            0,
+            4,
+            op::POP,
+            op::LOOP,
+            0,
+            10,
+            op::POP,
+            op::CONSTANT,
+            answer as u8,
+            op::RETURN,
+        ] {
+            chunk.write(byte, 1);
+        }
+        assert!(annotated_disassembly(&chunk, "loop", "").contains("OP_LOOP   10 -> 0"));
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Number(42.0));
+        assert_eq!(count.get(), 0);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    pub fn disassemble_mixed_constants() {
+        let mut chunk = Chunk::default();
+        for value in [
+            Value::String(String::from("hi there")),
+            Value::Boolean(false),
+            Value::Number(2.5),
+            Value::Nil,
+            Value::List(vec![Value::Number(1.0), Value::Boolean(true)]),
+        ] {
+            let constant = chunk.add_constant(value) as u8;
+            chunk.write(op::CONSTANT, 1);
+            chunk.write(constant, 1);
+        }
+        chunk.write(op::RETURN, 1);
+        let mut out = String::new();
+        write_chunk(&mut out, &chunk, "constants", None).unwrap();
+        assert_eq!(
+            out,
+            "\
+== constants ==
+0000    1 OP_CONSTANT    0 'hi there'
+0002    | OP_CONSTANT    1 'false'
+0004    | OP_CONSTANT    2 '2.5'
+0006    | OP_CONSTANT    3 'nil'
+0008    | OP_CONSTANT    4 '[1, true]'
+0010    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    pub fn disassemble_loop() {
+        let source = "{ var i = 0; while (i < 3) i = i + 1; }";
+        let mut compiler = Compiler::default();
+        compiler.set_line(1);
+        for statement in Parser::new(source, Scanner::new(source)) {
+            compiler.statement(&statement.value.unwrap()).unwrap();
+        }
+        assert_eq!(
+            annotated_disassembly(&compiler.finish(), "loop", source),
+            "\
+== loop ==
+// 1: { var i = 0; while (i < 3) i = i + 1; }
+0000    1 OP_CONSTANT    0 '0'
+0002    | OP_GET_LOCAL    0
+0004    | OP_CONSTANT    1 '3'
+0006    | OP_LESS
+0007    | OP_JUMP_IF_FALSE   12 -> 22
+0010    | OP_POP
+0011    | OP_GET_LOCAL    0
+0013    | OP_CONSTANT    2 '1'
+0015    | OP_ADD
+0016    | OP_SET_LOCAL    0
+0018    | OP_POP
+0019    | OP_LOOP   20 -> 2
+0022    | OP_POP
+0023    | OP_POP
+0024    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    pub fn compile_while_loop() {
+        let source = "while (false) { 1; }";
+        let mut compiler = Compiler::default();
+        for statement in Parser::new(source, Scanner::new(source)) {
+            compiler.statement(&statement.value.unwrap()).unwrap();
+        }
+        let chunk = compiler.finish();
+        assert_eq!(
+            chunk.code,
+            [
+                op::FALSE,
+                op::JUMP_IF_FALSE,
+                0,
+                7,
+                op::POP,
+                op::CONSTANT,
+                0,
+                op::POP,
+                op::LOOP,
+                0,
+                11,
+                op::POP,
+                op::RETURN,
+            ]
+        );
+        let mut vm = VirtualMachine::default();
+        assert_eq!(vm.run(Rc::new(chunk)).unwrap(), Value::Nil);
+        assert!(vm.stack.is_empty());
+    }
+
+    // Compiles a program whose value is that of its last statement, if that
+    // is an expression.
+    fn run_program(source: &str) -> Result<Value, RuntimeError> {
+        let mut statements = Parser::new(source, Scanner::new(source))
+            .map(|statement| statement.value.unwrap())
+            .collect::<Vec<_>>();
+        let last = match statements.last() {
+            Some(Statement::Expression(_)) => statements.pop(),
+            _ => None,
+        };
+        let mut compiler = Compiler::default();
+        compiler.set_line(1);
+        for statement in &statements {
+            compiler.statement(statement).unwrap();
+        }
+        if let Some(Statement::Expression(expression)) = last {
+            compiler.expression(&expression).unwrap();
+        }
+        VirtualMachine::default().run(Rc::new(compiler.finish()))
+    }
+
+    #[test]
+    pub fn global_variables() {
+        for (source, expected) in [
+            ("var a = 1; a + 1", Value::Number(2.0)),
+            ("var a; a", Value::Nil),
+            ("var a = 1; a = a + 2; a * 2", Value::Number(6.0)),
+            ("var a = 1; var b = a = 3; a + b", Value::Number(6.0)),
+            (
+                "var a = 1; var a = \"again\"; a",
+                Value::String(String::from("again")),
+            ),
+        ] {
+            assert_eq!(run_program(source).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    pub fn local_variables() {
+        for (source, expected) in [
+            (
+                "var result; { var a = 1; { var b = a + 10; var a = b; result = a; } }; result",
+                Value::Number(11.0),
+            ),
+            (
+                "var result; { var a = 1; { var a = 2; } result = a; }; result",
+                Value::Number(1.0),
+            ),
+            (
+                "var result; { var a = 1; var b; b = a = 3; result = a + b; }; result",
+                Value::Number(6.0),
+            ),
+            (
+                "var a = \"global\"; { var a = 1; } a",
+                Value::String(String::from("global")),
+            ),
+        ] {
+            assert_eq!(run_program(source).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    pub fn read_local_in_own_initializer() {
+        let source = "{ var a = a; }";
+        let statement = Parser::new(source, Scanner::new(source))
+            .next()
+            .unwrap()
+            .value
+            .unwrap();
+        assert!(matches!(
+            Compiler::default().statement(&statement),
+            Err(compiler::Error::ReadInOwnInitializer(_))
+        ));
+    }
+
+    #[test]
+    pub fn invalid_repeat_count() {
+        for (source, expected) in [("\"ab\" * 2.5", 2.5), ("\"ab\" * 100000000000", 1e11)] {
+            assert!(
+                matches!(
+                    run_program(source),
+                    Err(RuntimeError {
+                        error: Error::RepeatCount(count),
+                        line: 1
+                    }) if count == expected
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    pub fn undefined_global() {
+        for source in ["a", "var b = a;", "a = 1;", "var b = 1; b = c = 2;"] {
+            assert!(
+                matches!(
+                    run_program(source),
+                    Err(RuntimeError {
+                        error: Error::UndefinedVariable(ref name),
+                        line: 1
+                    }) if name == "a" || name == "c"
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    pub fn disassemble_jumps() {
+        let source = "true and false or nil";
+        assert_eq!(
+            annotated_disassembly(&compile(source), "logic", source),
+            "\
+== logic ==
+// 1: true and false or nil
+0000    1 OP_TRUE
+0001    | OP_JUMP_IF_FALSE    2 -> 6
+0004    | OP_POP
+0005    | OP_FALSE
+0006    | OP_JUMP_IF_FALSE    3 -> 12
+0009    | OP_JUMP    2 -> 14
+0012    | OP_POP
+0013    | OP_NIL
+0014    | OP_RETURN
+"
+        );
     }
 }
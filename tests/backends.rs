@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use loxer::{
+    ast::Statement, compiler::Compiler, interpreter::Interpreter, parse, vm::VirtualMachine, Value,
+};
+
+// Runs `source` through both the tree-walker and the compiler and VM, which
+// must agree on the value of its last statement, an expression.
+fn cross_check(source: &str) -> Value {
+    let (mut statements, errors) = parse(source);
+    assert!(errors.is_empty(), "{}", source);
+    let Some(Statement::Expression(result)) = statements.pop().map(|statement| statement.value)
+    else {
+        panic!("{} should end with an expression", source);
+    };
+
+    let mut compiler = Compiler::default();
+    compiler.set_line(1);
+    for statement in &statements {
+        compiler.statement(&statement.value).unwrap();
+    }
+    compiler.expression(&result).unwrap();
+    let compiled = VirtualMachine::default()
+        .run(Rc::new(compiler.finish()))
+        .unwrap();
+
+    let mut interpreter = Interpreter::default();
+    for statement in statements {
+        interpreter.execute(statement.value).unwrap();
+    }
+    let interpreted = interpreter.eval(&result).unwrap();
+
+    assert_eq!(interpreted, compiled, "{}", source);
+    interpreted
+}
+
+#[test]
+fn arithmetic() {
+    for (source, expected) in [
+        ("1 + 2 * 3 - 4 / 8", 6.5),
+        ("-(5 - 7) * 2.5 + 10 / 4", 7.5),
+        ("1 / 3 * 3 - 1", 1.0 / 3.0 * 3.0 - 1.0),
+        ("0.1 + 0.2", 0.1 + 0.2),
+        ("2 * 2 * 2 * 2 * 2 * 2 * 2 * 2 * 2 * 2 - 1000", 24.0),
+    ] {
+        assert_eq!(cross_check(source), Value::Number(expected), "{}", source);
+    }
+}
+
+#[test]
+fn comparisons() {
+    for (source, expected) in [
+        ("1 < 2", true),
+        ("2 <= 2 and 3 >= 4", false),
+        ("(1 < 2) == !(3 >= 4)", true),
+        ("1 + 1 != 2 or 0.1 + 0.2 > 0.3", true),
+        ("\"a\" + \"b\" == \"ab\"", true),
+        ("nil == false", false),
+    ] {
+        assert_eq!(cross_check(source), Value::Boolean(expected), "{}", source);
+    }
+}
+
+#[test]
+fn nested_grouping() {
+    for (source, expected) in [
+        ("((((1 + 2) * (3 - (4 - 5))) / (6)) - -(-(7)))", -5.0),
+        ("(((((((((((1)))))))))))", 1.0),
+        ("-(-(-(2 * (3 + (4 * (5 - (6 / (7 + 1))))))))", -40.0),
+    ] {
+        assert_eq!(cross_check(source), Value::Number(expected), "{}", source);
+    }
+}
+
+#[test]
+fn loops_and_variables() {
+    let source = "
+        var i = 0;
+        var sum = 0;
+        while (i < 100) {
+            var square = i * i;
+            sum = sum + square;
+            i = i + 1;
+        }
+        sum";
+    assert_eq!(cross_check(source), Value::Number(328350.0));
+}
@@ -0,0 +1,150 @@
+use std::{cell::RefCell, rc::Rc};
+
+use loxer::{
+    environment::Environment, interpret, interpret_all, is_incomplete, native, parse, parser, span,
+    Error, Interpreter, Value,
+};
+
+#[test]
+fn interpret_addition() {
+    assert_eq!(interpret("1 + 2").unwrap(), vec![Value::Number(3.0)]);
+}
+
+#[test]
+fn interpret_runtime_error() {
+    let error = interpret("1 + true").unwrap_err();
+    assert!(matches!(error.value, Error::Runtime(_)));
+    assert_eq!((error.span.start, error.span.end), (4, 8));
+}
+
+#[test]
+fn runtime_error_underlines_operand() {
+    let source = "1 + true";
+    let error = interpret(source).unwrap_err();
+    let lines = span::LineMap::new(source);
+    assert_eq!(
+        span::underline(source, &lines, error.span),
+        "1 + true\n    ^^^^\n"
+    );
+}
+
+#[test]
+fn runtime_error_spans() {
+    for (source, expected) in [
+        ("-\"a\";", "\"a\""),
+        ("print 1 + (nil - 2);", "nil"),
+        ("print 1 + [1, 2][\"x\"];", "[1, 2][\"x\"]"),
+        ("var x = 1; print x + y;", "y"),
+        ("print (1 + 2)(3);", "(1 + 2)"),
+        ("fun f(a) { return a * nil; } print f(2) + 1;", "f(2)"),
+    ] {
+        let error = interpret(source).unwrap_err();
+        assert!(matches!(error.value, Error::Runtime(_)));
+        assert_eq!(error.span.lexeme(source), expected, "{}", source);
+    }
+}
+
+#[test]
+fn interpret_collects_every_error() {
+    let source = "var = 1;\nprint (;\nprint 1 +* 2;\nprint 4;\n5";
+    let (values, errors) = interpret_all(source);
+    assert_eq!(values, vec![Value::Number(5.0)]);
+    assert_eq!(errors.len(), 3);
+    assert!(errors
+        .iter()
+        .all(|error| matches!(error.value, Error::Parse(_))));
+    let spans = errors
+        .iter()
+        .map(|error| (error.span.start, error.span.end))
+        .collect::<Vec<_>>();
+    assert!(
+        spans.windows(2).all(|pair| pair[0].1 <= pair[1].0),
+        "{:?}",
+        spans
+    );
+
+    let (values, errors) = interpret_all("1 + nil; var x = 2; x +; x - true; x");
+    assert_eq!(values, vec![Value::Number(2.0)]);
+    assert!(matches!(
+        errors.iter().map(|error| &error.value).collect::<Vec<_>>()[..],
+        [Error::Runtime(_), Error::Parse(_), Error::Runtime(_)]
+    ));
+}
+
+#[test]
+fn parse_collects_errors() {
+    let (statements, errors) = parse("1 +");
+    assert!(statements.is_empty());
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0].value, parser::Error::UnexpectedEof));
+    assert_eq!((errors[0].span.start, errors[0].span.end), (0, 3));
+}
+
+#[test]
+fn repl_lines_share_state() {
+    let mut interpreter = Interpreter::default();
+    assert_eq!(interpreter.interpret("var x = 10;").unwrap(), vec![]);
+    assert_eq!(
+        interpreter.interpret("x * 2").unwrap(),
+        vec![Value::Number(20.0)]
+    );
+    assert_eq!(
+        interpreter.interpret("x = x + 1; x").unwrap(),
+        vec![Value::Number(11.0), Value::Number(11.0)]
+    );
+}
+
+#[test]
+fn lines_share_an_environment() {
+    let mut globals = Environment::default();
+    native::define_globals(&mut globals);
+    let environment = Rc::new(RefCell::new(globals));
+    let lines = [
+        "var x = 10;",
+        "fun double(n) { return n * 2; }",
+        "double(x)",
+        "x = 1;",
+        "x + len(\"ab\")",
+    ];
+    let outputs = lines
+        .iter()
+        .map(|line| {
+            Interpreter::with_environment(environment.clone())
+                .interpret(line)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outputs,
+        [
+            vec![],
+            vec![],
+            vec![Value::Number(20.0)],
+            vec![Value::Number(1.0)],
+            vec![Value::Number(3.0)]
+        ]
+    );
+}
+
+#[test]
+fn continue_incomplete_input() {
+    assert!(is_incomplete("1 +\n"));
+    assert!(is_incomplete("fun f() {\n"));
+    assert!(is_incomplete("print f(1,\n"));
+    assert!(is_incomplete("f(g(x), 1\n"));
+    assert!(!is_incomplete("f(1 2;\n"));
+    assert!(!is_incomplete("1 + )\n"));
+    assert!(!is_incomplete("1 +\n2\n"));
+    assert_eq!(interpret("1 +\n2\n").unwrap(), vec![Value::Number(3.0)]);
+}
+
+#[test]
+fn unterminated_string_span() {
+    let (_, errors) = parse("print 1;\nprint \"no end");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        errors[0].value,
+        parser::Error::UnterminatedString(_)
+    ));
+    assert_eq!((errors[0].span.start, errors[0].span.end), (15, 22));
+}
@@ -0,0 +1,48 @@
+use std::rc::Rc;
+
+use loxer::{
+    ast::{Expression, Statement},
+    compiler::Compiler,
+    interpreter::Interpreter,
+    parser::Parser,
+    scanner::{Scanner, Token},
+    span::LineMap,
+    value::Value,
+    vm::VirtualMachine,
+};
+
+fn parse_expression(source: &str) -> Expression {
+    let mut parser = Parser::new(
+        source,
+        Scanner::new(source).filter(|token| token.value != Ok(Token::Comment)),
+    );
+    match parser.next().unwrap().value.unwrap() {
+        Statement::Expression(expression) => expression,
+        statement => panic!("expected an expression, got {:?}", statement),
+    }
+}
+
+#[test]
+fn tree_walker_from_modules() {
+    let expression = parse_expression("(1 + 2) * 3 // comment");
+    let value = Interpreter::default().eval(&expression).unwrap();
+    assert_eq!(value, Value::Number(9.0));
+}
+
+#[test]
+fn virtual_machine_from_modules() {
+    let mut compiler = Compiler::default();
+    compiler
+        .expression(&parse_expression("\"a\" + \"b\""))
+        .unwrap();
+    let mut vm = VirtualMachine::default();
+    let value = vm.run(Rc::new(compiler.finish())).unwrap();
+    assert_eq!(value, Value::String(String::from("ab")));
+}
+
+#[test]
+fn line_map_from_modules() {
+    let lines = LineMap::new("a\nb\n");
+    assert_eq!(lines.line(2), 1);
+    assert_eq!(lines.line_range(1), 2..3);
+}
@@ -0,0 +1,68 @@
+// Executable documentation of operator precedence: each source is parsed and
+// evaluated through the public API and its result checked.
+use loxer::{ast::Statement, eval, Parser, Scanner, Value};
+
+fn evaluate(source: &str) -> Value {
+    let statement = Parser::new(source, Scanner::new(source))
+        .next()
+        .expect("no statement")
+        .value
+        .unwrap_or_else(|error| panic!("{}: {}", source, error));
+    match statement {
+        Statement::Expression(expression) => {
+            eval(expression).unwrap_or_else(|error| panic!("{}: {}", source, error))
+        }
+        statement => panic!("{}: expected an expression, got {:?}", source, statement),
+    }
+}
+
+#[test]
+fn arithmetic() {
+    for (source, expected) in [
+        // Factors bind tighter than terms.
+        ("2 + 3 * 4", 14.0),
+        ("2 * 3 + 4", 10.0),
+        ("(2 + 3) * 4", 20.0),
+        ("1 + 8 / 4", 3.0),
+        // Both levels are left-associative.
+        ("10 - 4 - 3", 3.0),
+        ("48 / 4 / 2", 6.0),
+        ("2 * 6 / 3 * 2", 8.0),
+        // Unary operators bind tighter than any binary one.
+        ("-2 * 3", -6.0),
+        ("-2 - -2", 0.0),
+        ("2 * -3 + 1", -5.0),
+        ("- -2 + 1", 3.0),
+        ("-(2 + 3) * 2", -10.0),
+        ("+2 - +3", -1.0),
+        // Bitwise operators and shifts sit between comparison and equality.
+        ("1 | 2 + 4", 7.0),
+        ("1 << 2 + 1", 8.0),
+        ("6 & 3 | 8", 10.0),
+        ("5 ^ 1 & 3", 4.0),
+    ] {
+        assert_eq!(evaluate(source), Value::Number(expected), "{}", source);
+    }
+}
+
+#[test]
+fn comparison_and_equality() {
+    for (source, expected) in [
+        ("1 + 2 == 3", true),
+        ("1 + 2 < 2 * 2", true),
+        ("-1 > -2", true),
+        // Comparison binds tighter than equality.
+        ("1 < 2 == 2 < 3", true),
+        ("1 < 2 != true", false),
+        ("6 & 3 == 2", true),
+        // `!` applies to its operand before equality is checked.
+        ("!true == false", true),
+        ("!(1 > 2)", true),
+        ("!nil == !false", true),
+        // Equality is left-associative.
+        ("1 == 1 == true", true),
+        ("2 == 1 == false", true),
+    ] {
+        assert_eq!(evaluate(source), Value::Boolean(expected), "{}", source);
+    }
+}
@@ -0,0 +1,47 @@
+use loxer::{
+    interpret, parse,
+    span::{underline, LineMap},
+};
+
+fn check(source: &str) {
+    let lines = LineMap::new(source);
+    let (_, errors) = parse(source);
+    for error in &errors {
+        underline(source, &lines, error.span);
+        error.value.to_string();
+    }
+    if let Err(error) = interpret(source) {
+        underline(source, &lines, error.span);
+        error.value.to_string();
+    }
+}
+
+#[test]
+fn multiple_dots_in_number() {
+    check("1.2.3");
+    assert!(interpret("1.2.3").is_err());
+}
+
+#[test]
+fn lone_quote() {
+    check("\"");
+    assert!(interpret("\"").is_err());
+}
+
+#[test]
+fn unclosed_parens() {
+    check("((");
+    assert!(interpret("((").is_err());
+}
+
+#[test]
+fn byte_order_mark() {
+    check("\u{FEFF}");
+    assert!(interpret("\u{FEFF}").is_err());
+}
+
+#[test]
+fn empty_input() {
+    check("");
+    assert!(interpret("").unwrap().is_empty());
+}